@@ -2,6 +2,6 @@
 
 set -e
 cargo build --package runtime --release
+cargo build --package pyt --release
 mkdir -p target/asm
-nasm -felf64 pyt/hello.asm -o target/asm/hello.o
-ld target/asm/hello.o target/release/libruntime.a -o target/asm/hello
\ No newline at end of file
+target/release/pyt build pyt/hello.pyt -o target/asm/hello
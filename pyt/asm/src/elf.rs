@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use Program;
+use encoder::{self, Reloc, RelocKind};
+
+// Section indices, in the order of the section header table.
+const TEXT: u16 = 1;
+const DATA: u16 = 2;
+const SYMTAB: u32 = 4;
+const STRTAB: u32 = 5;
+const SHSTRTAB: u16 = 7;
+const N_SECTIONS: u16 = 8;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const R_X86_64_PC32: u64 = 2;
+const R_X86_64_PLT32: u64 = 4;
+
+const HEADER_SIZE: usize = 64;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+struct Symbol {
+    name: u32,
+    info: u8,
+    section: u16,
+    value: u64,
+    size: u64,
+}
+
+/// Writes a relocatable object with `.text`, `.data` and the sections
+/// describing them. The symbol table starts with the local section
+/// symbols, followed by the global definitions and the externs.
+pub fn write_object(program: &Program) -> Vec<u8> {
+    let mut strtab = StringTable::new();
+    let mut symbols = vec![
+        Symbol { name: 0, info: 0, section: 0, value: 0, size: 0 },
+        Symbol { name: 0, info: STB_LOCAL << 4 | STT_SECTION, section: TEXT, value: 0, size: 0 },
+        Symbol { name: 0, info: STB_LOCAL << 4 | STT_SECTION, section: DATA, value: 0, size: 0 },
+    ];
+    let first_global = symbols.len() as u32;
+
+    let mut text = Vec::new();
+    let mut relocs: Vec<Reloc> = Vec::new();
+    for function in program.functions.iter() {
+        let start = text.len();
+        for inst in function.body.iter() {
+            encoder::encode(inst, &mut text, &mut relocs);
+        }
+        symbols.push(Symbol {
+            name: strtab.add(&function.name),
+            info: STB_GLOBAL << 4 | STT_FUNC,
+            section: TEXT,
+            value: start as u64,
+            size: (text.len() - start) as u64,
+        });
+    }
+    let mut data = Vec::new();
+    for item in program.data.iter() {
+        symbols.push(Symbol {
+            name: strtab.add(&item.name),
+            info: STB_GLOBAL << 4 | STT_OBJECT,
+            section: DATA,
+            value: data.len() as u64,
+            size: item.bytes.len() as u64,
+        });
+        data.extend_from_slice(&item.bytes);
+    }
+    for name in program.externs.iter() {
+        symbols.push(Symbol {
+            name: strtab.add(name),
+            info: STB_GLOBAL << 4 | STT_NOTYPE,
+            section: 0,
+            value: 0,
+            size: 0,
+        });
+    }
+    let symbol_index: HashMap<&str, u64> = program.functions.iter().map(|f| &f.name)
+        .chain(program.data.iter().map(|d| &d.name))
+        .chain(program.externs.iter())
+        .enumerate()
+        .map(|(i, name)| (&name[..], first_global as u64 + i as u64))
+        .collect();
+
+    let mut rela = Vec::new();
+    for reloc in relocs.iter() {
+        let symbol = match symbol_index.get(&reloc.symbol[..]) {
+            Some(&symbol) => symbol,
+            None => panic!("undefined symbol `{}`", reloc.symbol),
+        };
+        let ty = match reloc.kind {
+            RelocKind::Pc32 => R_X86_64_PC32,
+            RelocKind::Plt32 => R_X86_64_PLT32,
+        };
+        u64(&mut rela, reloc.offset as u64);
+        u64(&mut rela, symbol << 32 | ty);
+        u64(&mut rela, reloc.addend as u64);
+    }
+    let mut symtab = Vec::new();
+    for symbol in symbols.iter() {
+        u32(&mut symtab, symbol.name);
+        symtab.push(symbol.info);
+        symtab.push(0);
+        u16(&mut symtab, symbol.section);
+        u64(&mut symtab, symbol.value);
+        u64(&mut symtab, symbol.size);
+    }
+
+    let mut shstrtab = StringTable::new();
+    let names: Vec<u32> = [".text", ".data", ".rela.text", ".symtab", ".strtab", ".note.GNU-stack", ".shstrtab"].iter()
+        .map(|name| shstrtab.add(name))
+        .collect();
+    let sections = [
+        Section { ty: SHT_PROGBITS, flags: SHF_ALLOC | SHF_EXECINSTR, link: 0, info: 0,
+                  align: 16, entsize: 0, contents: &text },
+        Section { ty: SHT_PROGBITS, flags: SHF_WRITE | SHF_ALLOC, link: 0, info: 0,
+                  align: 8, entsize: 0, contents: &data },
+        Section { ty: SHT_RELA, flags: SHF_INFO_LINK, link: SYMTAB, info: TEXT as u32,
+                  align: 8, entsize: RELA_SIZE, contents: &rela },
+        Section { ty: SHT_SYMTAB, flags: 0, link: STRTAB, info: first_global,
+                  align: 8, entsize: SYMBOL_SIZE, contents: &symtab },
+        Section { ty: SHT_STRTAB, flags: 0, link: 0, info: 0,
+                  align: 1, entsize: 0, contents: &strtab.bytes },
+        // Marks the stack as not executable.
+        Section { ty: SHT_PROGBITS, flags: 0, link: 0, info: 0,
+                  align: 1, entsize: 0, contents: &[] },
+        Section { ty: SHT_STRTAB, flags: 0, link: 0, info: 0,
+                  align: 1, entsize: 0, contents: &shstrtab.bytes },
+    ];
+    assert_eq!(sections.len(), N_SECTIONS as usize - 1);
+
+    // The header, the contents of the sections, and the section headers,
+    // starting with the null one.
+    let mut out = vec![0; HEADER_SIZE];
+    let mut headers = vec![0; SECTION_HEADER_SIZE];
+    for (section, &name) in sections.iter().zip(names.iter()) {
+        align(&mut out, section.align);
+        section.write_header(&mut headers, name, out.len());
+        out.extend_from_slice(section.contents);
+    }
+    align(&mut out, 8);
+    let headers_offset = out.len();
+    out.extend_from_slice(&headers);
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    // Magic, 64 bit, little endian, version 1, System V ABI.
+    header.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+    header.extend_from_slice(&[0; 8]);
+    u16(&mut header, 1); // ET_REL
+    u16(&mut header, 62); // EM_X86_64
+    u32(&mut header, 1);
+    u64(&mut header, 0); // entry
+    u64(&mut header, 0); // program headers
+    u64(&mut header, headers_offset as u64);
+    u32(&mut header, 0); // flags
+    u16(&mut header, HEADER_SIZE as u16);
+    u16(&mut header, 0);
+    u16(&mut header, 0);
+    u16(&mut header, SECTION_HEADER_SIZE as u16);
+    u16(&mut header, N_SECTIONS);
+    u16(&mut header, SHSTRTAB);
+    out[..HEADER_SIZE].copy_from_slice(&header);
+    out
+}
+
+struct Section<'a> {
+    ty: u32,
+    flags: u64,
+    link: u32,
+    info: u32,
+    align: usize,
+    entsize: usize,
+    contents: &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    fn write_header(&self, out: &mut Vec<u8>, name: u32, offset: usize) {
+        u32(out, name);
+        u32(out, self.ty);
+        u64(out, self.flags);
+        u64(out, 0); // address
+        u64(out, offset as u64);
+        u64(out, self.contents.len() as u64);
+        u32(out, self.link);
+        u32(out, self.info);
+        u64(out, self.align as u64);
+        u64(out, self.entsize as u64);
+    }
+}
+
+/// Null terminated names, referred to by offsets. The first one is empty.
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> StringTable {
+        StringTable { bytes: vec![0] }
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+fn align(out: &mut Vec<u8>, align: usize) {
+    let padding = (align - out.len() % align) % align;
+    let len = out.len() + padding;
+    out.resize(len, 0);
+}
+
+fn u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[test]
+fn test_write_object() {
+    use {Function, Data, Inst};
+
+    let program = Program {
+        externs: vec!["exit".to_owned()],
+        functions: vec![Function {
+            name: "_start".to_owned(),
+            body: vec![Inst::Lea(::Reg::Rdi, "message".to_owned()), Inst::Call("exit".to_owned())],
+        }],
+        data: vec![Data { name: "message".to_owned(), bytes: b"hi".to_vec() }],
+    };
+    let object = program.to_object();
+    assert_eq!(&object[..4], b"\x7FELF");
+    assert_eq!(read_u16(&object, 16), 1);
+    assert_eq!(read_u16(&object, 18), 62);
+    assert_eq!(read_u16(&object, 60), N_SECTIONS);
+
+    // `.text` comes right after the header.
+    assert_eq!(&object[64..76], &[0x48, 0x8D, 0x3D, 0, 0, 0, 0, 0xE8, 0, 0, 0, 0]);
+    // `.data` follows, aligned to 8 bytes.
+    assert_eq!(&object[80..82], b"hi");
+    // Relocations against `message` (symbol 4) and `exit` (symbol 5).
+    let rela = &object[88..88 + 2 * RELA_SIZE];
+    assert_eq!(&rela[..16], &[3, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0]);
+    assert_eq!(&rela[24..40], &[8, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0, 0]);
+}
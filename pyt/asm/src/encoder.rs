@@ -0,0 +1,233 @@
+use {Inst, Operand, Reg};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    /// `R_X86_64_PC32`, a reference relative to the next instruction.
+    Pc32,
+    /// `R_X86_64_PLT32`, a call which the linker may route through the PLT.
+    Plt32,
+}
+
+/// A 32 bit field at `offset` which the linker sets to
+/// `symbol + addend - offset`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reloc {
+    pub offset: usize,
+    pub symbol: String,
+    pub kind: RelocKind,
+    pub addend: i64,
+}
+
+/// Appends the machine code of `inst` to `code`. Operand combinations
+/// which x86-64 can't encode, like two memory operands or a 64 bit
+/// immediate for anything but `mov` to a register, are a bug of the
+/// caller.
+pub fn encode(inst: &Inst, code: &mut Vec<u8>, relocs: &mut Vec<Reloc>) {
+    match *inst {
+        Inst::Mov(Operand::Reg(dst), Operand::Imm(value)) => {
+            if value <= u32::MAX as u64 {
+                // Writing a 32 bit register zeroes the upper half.
+                if number(dst) >= 8 {
+                    code.push(0x41);
+                }
+                code.push(0xB8 + (number(dst) & 7));
+                code.extend_from_slice(&(value as u32).to_le_bytes());
+            } else if let Some(value) = imm32(value) {
+                modrm(code, &[0xC7], 0, Operand::Reg(dst));
+                code.extend_from_slice(&value.to_le_bytes());
+            } else {
+                code.push(rex(0, number(dst)));
+                code.push(0xB8 + (number(dst) & 7));
+                code.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        Inst::Mov(dst @ Operand::Mem(..), Operand::Imm(value)) => match imm32(value) {
+            Some(value) => {
+                modrm(code, &[0xC7], 0, dst);
+                code.extend_from_slice(&value.to_le_bytes());
+            }
+            None => invalid(inst),
+        },
+        Inst::Mov(Operand::Reg(dst), src) => modrm(code, &[0x8B], number(dst), src),
+        Inst::Mov(dst @ Operand::Mem(..), Operand::Reg(src)) => modrm(code, &[0x89], number(src), dst),
+        Inst::Add(dst, src) => arithmetic(inst, code, 0x03, 0, dst, src),
+        Inst::Sub(dst, src) => arithmetic(inst, code, 0x2B, 5, dst, src),
+        Inst::Imul(dst, Operand::Imm(value)) => match imm32(value) {
+            Some(value) if value as i8 as i32 == value => {
+                modrm(code, &[0x6B], number(dst), Operand::Reg(dst));
+                code.push(value as u8);
+            }
+            Some(value) => {
+                modrm(code, &[0x69], number(dst), Operand::Reg(dst));
+                code.extend_from_slice(&value.to_le_bytes());
+            }
+            None => invalid(inst),
+        },
+        Inst::Imul(dst, src) => modrm(code, &[0x0F, 0xAF], number(dst), src),
+        Inst::Div(src) => modrm(code, &[0xF7], 6, Operand::Reg(src)),
+        Inst::Neg(dst) => modrm(code, &[0xF7], 3, Operand::Reg(dst)),
+        Inst::Lea(dst, ref symbol) => {
+            code.push(rex(number(dst), 0));
+            code.push(0x8D);
+            // `mod = 00, r/m = 101` is `[rip + disp32]`.
+            code.push((number(dst) & 7) << 3 | 0b101);
+            rel32(code, relocs, symbol, RelocKind::Pc32);
+        }
+        Inst::Call(ref symbol) => {
+            code.push(0xE8);
+            rel32(code, relocs, symbol, RelocKind::Plt32);
+        }
+        Inst::Syscall => code.extend_from_slice(&[0x0F, 0x05]),
+        Inst::Ret => code.push(0xC3),
+        Inst::Mov(..) => invalid(inst),
+    }
+}
+
+/// `add` and `sub`: `opcode` is the `reg, r/m` form and `ext` is the
+/// opcode extension of the immediate forms.
+fn arithmetic(inst: &Inst, code: &mut Vec<u8>, opcode: u8, ext: u8, dst: Reg, src: Operand) {
+    match src {
+        Operand::Imm(value) => match imm32(value) {
+            Some(value) if value as i8 as i32 == value => {
+                modrm(code, &[0x83], ext, Operand::Reg(dst));
+                code.push(value as u8);
+            }
+            Some(value) => {
+                modrm(code, &[0x81], ext, Operand::Reg(dst));
+                code.extend_from_slice(&value.to_le_bytes());
+            }
+            None => invalid(inst),
+        },
+        src => modrm(code, &[opcode], number(dst), src),
+    }
+}
+
+/// Emits a 64 bit instruction with a ModRM byte, where `reg` is either
+/// a register or an opcode extension.
+fn modrm(code: &mut Vec<u8>, opcode: &[u8], reg: u8, rm: Operand) {
+    match rm {
+        Operand::Reg(rm) => {
+            code.push(rex(reg, number(rm)));
+            code.extend_from_slice(opcode);
+            code.push(0b11 << 6 | (reg & 7) << 3 | (number(rm) & 7));
+        }
+        Operand::Mem(base, disp) => {
+            let base = number(base);
+            code.push(rex(reg, base));
+            code.extend_from_slice(opcode);
+            // `rbp` and `r13` without a displacement mean `rip` relative.
+            let mode = if disp == 0 && base & 7 != 5 {
+                0b00
+            } else if disp as i8 as i32 == disp {
+                0b01
+            } else {
+                0b10
+            };
+            code.push(mode << 6 | (reg & 7) << 3 | (base & 7));
+            // `rsp` and `r12` as a base need a SIB byte.
+            if base & 7 == 4 {
+                code.push(0x24);
+            }
+            match mode {
+                0b01 => code.push(disp as u8),
+                0b10 => code.extend_from_slice(&disp.to_le_bytes()),
+                _ => {}
+            }
+        }
+        Operand::Imm(_) => unreachable!(),
+    }
+}
+
+/// The REX prefix with `W` set, which selects 64 bit operands.
+fn rex(reg: u8, rm: u8) -> u8 {
+    0x48 | (reg >> 3) << 2 | rm >> 3
+}
+
+fn rel32(code: &mut Vec<u8>, relocs: &mut Vec<Reloc>, symbol: &str, kind: RelocKind) {
+    relocs.push(Reloc {
+        offset: code.len(),
+        symbol: symbol.to_owned(),
+        kind: kind,
+        // The displacement is relative to the end of the instruction.
+        addend: -4,
+    });
+    code.extend_from_slice(&[0; 4]);
+}
+
+fn number(reg: Reg) -> u8 {
+    reg as u8
+}
+
+/// Immediates are sign extended from 32 bits.
+fn imm32(value: u64) -> Option<i32> {
+    let value = value as i64;
+    if value as i32 as i64 == value {
+        Some(value as i32)
+    } else {
+        None
+    }
+}
+
+fn invalid(inst: &Inst) -> ! {
+    panic!("can't encode `{}`", inst)
+}
+
+#[cfg(test)]
+fn check_encoding(inst: Inst, expected: &[u8]) {
+    let mut code = Vec::new();
+    encode(&inst, &mut code, &mut Vec::new());
+    assert!(code == expected, "`{}`: {:02x?} != {:02x?}", inst, code, expected);
+}
+
+#[test]
+fn test_encode() {
+    let mem = |base, disp| Operand::Mem(base, disp);
+    check_encoding(Inst::Mov(Operand::Reg(Reg::Rbp), Operand::Reg(Reg::Rsp)), &[0x48, 0x8B, 0xEC]);
+    check_encoding(Inst::Mov(Operand::Reg(Reg::R9), Operand::Reg(Reg::Rax)), &[0x4C, 0x8B, 0xC8]);
+    check_encoding(Inst::Mov(Operand::Reg(Reg::Rax), mem(Reg::Rbp, -8)), &[0x48, 0x8B, 0x45, 0xF8]);
+    check_encoding(Inst::Mov(mem(Reg::Rbp, -200), Operand::Reg(Reg::Rax)),
+                   &[0x48, 0x89, 0x85, 0x38, 0xFF, 0xFF, 0xFF]);
+    check_encoding(Inst::Mov(mem(Reg::Rbp, 0), Operand::Reg(Reg::Rcx)), &[0x48, 0x89, 0x4D, 0x00]);
+    check_encoding(Inst::Mov(Operand::Reg(Reg::Rdx), mem(Reg::Rsp, 0)), &[0x48, 0x8B, 0x14, 0x24]);
+    check_encoding(Inst::Mov(Operand::Reg(Reg::R12), mem(Reg::R12, 16)), &[0x4D, 0x8B, 0x64, 0x24, 0x10]);
+    check_encoding(Inst::Mov(mem(Reg::Rsp, 8), Operand::Imm(1)),
+                   &[0x48, 0xC7, 0x44, 0x24, 0x08, 0x01, 0x00, 0x00, 0x00]);
+
+    check_encoding(Inst::Mov(Operand::Reg(Reg::Rax), Operand::Imm(1)), &[0xB8, 0x01, 0x00, 0x00, 0x00]);
+    check_encoding(Inst::Mov(Operand::Reg(Reg::R15), Operand::Imm(0xFFFF_FFFF)), &[0x41, 0xBF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    check_encoding(Inst::Mov(Operand::Reg(Reg::Rcx), Operand::Imm(!0)),
+                   &[0x48, 0xC7, 0xC1, 0xFF, 0xFF, 0xFF, 0xFF]);
+    check_encoding(Inst::Mov(Operand::Reg(Reg::Rdi), Operand::Imm(1 << 40)),
+                   &[0x48, 0xBF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]);
+
+    check_encoding(Inst::Add(Reg::Rax, Operand::Reg(Reg::Rcx)), &[0x48, 0x03, 0xC1]);
+    check_encoding(Inst::Add(Reg::Rax, mem(Reg::Rbp, -16)), &[0x48, 0x03, 0x45, 0xF0]);
+    check_encoding(Inst::Sub(Reg::Rsp, Operand::Imm(16)), &[0x48, 0x83, 0xEC, 0x10]);
+    check_encoding(Inst::Sub(Reg::Rsp, Operand::Imm(4096)), &[0x48, 0x81, 0xEC, 0x00, 0x10, 0x00, 0x00]);
+    check_encoding(Inst::Add(Reg::Rsp, Operand::Imm(!0)), &[0x48, 0x83, 0xC4, 0xFF]);
+    check_encoding(Inst::Imul(Reg::Rax, Operand::Reg(Reg::Rcx)), &[0x48, 0x0F, 0xAF, 0xC1]);
+    check_encoding(Inst::Imul(Reg::Rax, Operand::Imm(10)), &[0x48, 0x6B, 0xC0, 0x0A]);
+    check_encoding(Inst::Div(Reg::Rcx), &[0x48, 0xF7, 0xF1]);
+    check_encoding(Inst::Neg(Reg::R8), &[0x49, 0xF7, 0xD8]);
+    check_encoding(Inst::Syscall, &[0x0F, 0x05]);
+    check_encoding(Inst::Ret, &[0xC3]);
+}
+
+#[test]
+fn test_encode_relocations() {
+    let mut code = vec![0x90];
+    let mut relocs = Vec::new();
+    encode(&Inst::Call("print".to_owned()), &mut code, &mut relocs);
+    encode(&Inst::Lea(Reg::Rsi, "buffer".to_owned()), &mut code, &mut relocs);
+    assert_eq!(code, [0x90, 0xE8, 0, 0, 0, 0, 0x48, 0x8D, 0x35, 0, 0, 0, 0]);
+    assert_eq!(relocs, vec![
+        Reloc { offset: 2, symbol: "print".to_owned(), kind: RelocKind::Plt32, addend: -4 },
+        Reloc { offset: 9, symbol: "buffer".to_owned(), kind: RelocKind::Pc32, addend: -4 },
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "can't encode `mov qword [rax], qword [rcx]`")]
+fn test_encode_invalid() {
+    encode(&Inst::Mov(Operand::Mem(Reg::Rax, 0), Operand::Mem(Reg::Rcx, 0)), &mut Vec::new(), &mut Vec::new());
+}
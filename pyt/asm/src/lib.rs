@@ -0,0 +1,170 @@
+//! A subset of x86-64 assembly, which can be printed as NASM source or
+//! encoded directly into an ELF64 relocatable object file.
+
+use std::fmt;
+
+mod encoder;
+mod elf;
+
+/// General purpose registers, in the order of their encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(u64),
+    /// `[base + disp]`
+    Mem(Reg, i32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    Mov(Operand, Operand),
+    Add(Reg, Operand),
+    Sub(Reg, Operand),
+    /// Signed multiplication, which gives the same low 64 bits as the
+    /// unsigned one.
+    Imul(Reg, Operand),
+    /// Unsigned division of `rdx:rax`, the quotient goes to `rax`.
+    Div(Reg),
+    Neg(Reg),
+    /// Loads the address of a symbol, relative to `rip`.
+    Lea(Reg, String),
+    Call(String),
+    Syscall,
+    Ret,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub body: Vec<Inst>,
+}
+
+#[derive(Debug)]
+pub struct Data {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+/// A single object file. All functions and data are global symbols,
+/// `externs` are the symbols defined elsewhere.
+#[derive(Debug)]
+pub struct Program {
+    pub externs: Vec<String>,
+    pub functions: Vec<Function>,
+    pub data: Vec<Data>,
+}
+
+impl Program {
+    /// Encodes the program as an ELF64 relocatable object file.
+    pub fn to_object(&self) -> Vec<u8> {
+        elf::write_object(self)
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reg::Rax => "rax",
+            Reg::Rcx => "rcx",
+            Reg::Rdx => "rdx",
+            Reg::Rbx => "rbx",
+            Reg::Rsp => "rsp",
+            Reg::Rbp => "rbp",
+            Reg::Rsi => "rsi",
+            Reg::Rdi => "rdi",
+            Reg::R8 => "r8",
+            Reg::R9 => "r9",
+            Reg::R10 => "r10",
+            Reg::R11 => "r11",
+            Reg::R12 => "r12",
+            Reg::R13 => "r13",
+            Reg::R14 => "r14",
+            Reg::R15 => "r15",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Reg(reg) => write!(f, "{}", reg),
+            Operand::Imm(value) => write!(f, "{}", value),
+            Operand::Mem(base, 0) => write!(f, "qword [{}]", base),
+            Operand::Mem(base, disp) if disp < 0 => write!(f, "qword [{} - {}]", base, -(disp as i64)),
+            Operand::Mem(base, disp) => write!(f, "qword [{} + {}]", base, disp),
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inst::Mov(dst, src) => write!(f, "mov {}, {}", dst, src),
+            Inst::Add(dst, src) => write!(f, "add {}, {}", dst, src),
+            Inst::Sub(dst, src) => write!(f, "sub {}, {}", dst, src),
+            Inst::Imul(dst, src) => write!(f, "imul {}, {}", dst, src),
+            Inst::Div(src) => write!(f, "div {}", src),
+            Inst::Neg(dst) => write!(f, "neg {}", dst),
+            Inst::Lea(dst, ref name) => write!(f, "lea {}, [rel {}]", dst, name),
+            Inst::Call(ref name) => write!(f, "call {}", name),
+            Inst::Syscall => write!(f, "syscall"),
+            Inst::Ret => write!(f, "ret"),
+        }
+    }
+}
+
+/// NASM syntax, in the same layout as a hand written file.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.functions.iter().map(|function| &function.name)
+            .chain(self.data.iter().map(|data| &data.name));
+        for name in names {
+            writeln!(f, "global {}", name)?;
+        }
+        for name in self.externs.iter() {
+            writeln!(f, "extern {}", name)?;
+        }
+        writeln!(f)?;
+        writeln!(f)?;
+        writeln!(f, "section .text")?;
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}:", function.name)?;
+            for inst in function.body.iter() {
+                writeln!(f, "    {}", inst)?;
+            }
+        }
+        if !self.data.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "section .data")?;
+            for data in self.data.iter() {
+                let bytes: Vec<String> = data.bytes.iter().map(|b| b.to_string()).collect();
+                writeln!(f, "{}: db {}", data.name, bytes.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
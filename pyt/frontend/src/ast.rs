@@ -0,0 +1,204 @@
+use syntax::{RstFile, Node, NodeType};
+
+use node::{NUMBER, ADD, SUB, MUL, DIV};
+
+pub use syntax::AstElement;
+pub use node::{
+    File, Stmt, Assignment, ExprStmt, Name,
+    Expr, LitExpr, NameExpr, PrefixExpr, BinExpr, CallExpr, ArgList, ParenExpr,
+};
+
+pub struct AstFile {
+    rst: RstFile
+}
+
+impl AstFile {
+    pub fn new(rst: RstFile) -> AstFile {
+        AstFile { rst: rst }
+    }
+
+    pub fn root(&self) -> File {
+        File::from_node(self.rst.root())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinOp {
+    fn from_ty(ty: NodeType) -> Option<BinOp> {
+        match ty {
+            ADD => Some(BinOp::Add),
+            SUB => Some(BinOp::Sub),
+            MUL => Some(BinOp::Mul),
+            DIV => Some(BinOp::Div),
+            _ => None,
+        }
+    }
+}
+
+impl<'f> Name<'f> {
+    pub fn text(&self) -> &'f str {
+        self.node().text()
+    }
+}
+
+impl<'f> LitExpr<'f> {
+    /// `None` if the number doesn't fit into 64 bits.
+    pub fn value(&self) -> Option<u64> {
+        self.node().children()
+            .find(|child| child.ty() == NUMBER)
+            .and_then(|number| number.text().parse().ok())
+    }
+}
+
+impl<'f> BinExpr<'f> {
+    pub fn lhs(&self) -> Option<Expr<'f>> {
+        self.node().children()
+            .take_while(|&child| !is_op(child))
+            .filter_map(Expr::cast)
+            .next()
+    }
+
+    pub fn op(&self) -> Option<BinOp> {
+        self.node().children().filter_map(|child| BinOp::from_ty(child.ty())).next()
+    }
+
+    /// The operand after the operator, so that `1 +` has no `rhs`.
+    pub fn rhs(&self) -> Option<Expr<'f>> {
+        self.node().children()
+            .skip_while(|&child| !is_op(child))
+            .filter_map(Expr::cast)
+            .next()
+    }
+}
+
+impl<'f> CallExpr<'f> {
+    /// The arguments, or nothing if the argument list is missing.
+    pub fn args(&self) -> Vec<Expr<'f>> {
+        self.arg_list().map(|arg_list| arg_list.args()).unwrap_or_default()
+    }
+}
+
+fn is_op(node: Node) -> bool {
+    BinOp::from_ty(node.ty()).is_some()
+}
+
+#[cfg(test)]
+fn parse(text: &str) -> AstFile {
+    AstFile::new(::parse(text.to_owned()))
+}
+
+#[test]
+fn test_ast() {
+    let file = parse("x = 1 - 2 * y\nprint(x)");
+    let stmts = file.root().stmts();
+    assert_eq!(stmts.len(), 2);
+    let assignment = match stmts[0] {
+        Stmt::Assignment(assignment) => assignment,
+        _ => panic!(),
+    };
+    assert_eq!(assignment.name().unwrap().node().text(), "x");
+    let bin_expr = match assignment.value() {
+        Some(Expr::BinExpr(bin_expr)) => bin_expr,
+        _ => panic!(),
+    };
+    assert_eq!(bin_expr.op(), Some(BinOp::Sub));
+    match bin_expr.lhs() {
+        Some(Expr::LitExpr(lit)) => assert_eq!(lit.value(), Some(1)),
+        _ => panic!(),
+    }
+    match bin_expr.rhs() {
+        Some(Expr::BinExpr(rhs)) => assert_eq!(rhs.op(), Some(BinOp::Mul)),
+        _ => panic!(),
+    }
+
+    let call = match stmts[1] {
+        Stmt::ExprStmt(stmt) => match stmt.expr() {
+            Some(Expr::CallExpr(call)) => call,
+            _ => panic!(),
+        },
+        _ => panic!(),
+    };
+    match call.callee() {
+        Some(Expr::NameExpr(callee)) => assert_eq!(callee.name().unwrap().text(), "print"),
+        _ => panic!(),
+    }
+    assert_eq!(call.args().len(), 1);
+}
+
+#[test]
+fn test_ast_debug() {
+    let file = parse("x = -(1)\nf(x, 92)");
+    let expected = "File { stmts: [\
+        Assignment { name: Some(Name(\"x\")), value: Some(PrefixExpr { expr: Some(ParenExpr { expr: Some(LitExpr(\"1\")) }) }) }, \
+        ExprStmt { expr: Some(CallExpr { callee: Some(NameExpr { name: Some(Name(\"f\")) }), arg_list: Some(ArgList { args: [NameExpr { name: Some(Name(\"x\")) }, LitExpr(\"92\")] }) }) }\
+    ] }";
+    assert_eq!(format!("{:?}", file.root()), expected);
+}
+
+#[test]
+fn test_ast_recovery() {
+    let file = parse("x = (1 +)\nprint(99999999999999999999)\ny =");
+    let stmts = file.root().stmts();
+    assert_eq!(stmts.len(), 3);
+    let bin_expr = match stmts[0] {
+        Stmt::Assignment(assignment) => match assignment.value() {
+            Some(Expr::ParenExpr(paren_expr)) => match paren_expr.expr() {
+                Some(Expr::BinExpr(bin_expr)) => bin_expr,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        },
+        _ => panic!(),
+    };
+    assert!(bin_expr.lhs().is_some());
+    assert_eq!(bin_expr.op(), Some(BinOp::Add));
+    assert!(bin_expr.rhs().is_none());
+
+    let call = match stmts[1] {
+        Stmt::ExprStmt(stmt) => match stmt.expr() {
+            Some(Expr::CallExpr(call)) => call,
+            _ => panic!(),
+        },
+        _ => panic!(),
+    };
+    match call.args()[..] {
+        [Expr::LitExpr(lit)] => assert_eq!(lit.value(), None),
+        _ => panic!(),
+    }
+
+    match stmts[2] {
+        Stmt::Assignment(assignment) => assert!(assignment.value().is_none()),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_ast_comments() {
+    let file = parse("x = # c\n5\nprint(\n# the value\nx)");
+    let stmts = file.root().stmts();
+    match stmts[0] {
+        Stmt::Assignment(assignment) => match assignment.value() {
+            Some(Expr::LitExpr(lit)) => assert_eq!(lit.value(), Some(5)),
+            _ => panic!(),
+        },
+        _ => panic!(),
+    }
+    let call = match stmts[1] {
+        Stmt::ExprStmt(stmt) => match stmt.expr() {
+            Some(Expr::CallExpr(call)) => call,
+            _ => panic!(),
+        },
+        _ => panic!(),
+    };
+    match call.args()[..] {
+        [Expr::NameExpr(name_expr)] => assert_eq!(name_expr.name().unwrap().text(), "x"),
+        _ => panic!(),
+    }
+}
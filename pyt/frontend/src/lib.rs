@@ -1,32 +1,77 @@
+#[macro_use]
 extern crate syntax;
 
 mod tokenizer;
 mod parser;
+pub mod ast;
 
 pub mod node {
-    use syntax::NodeType;
+    grammar! {
+        tokens {
+            NUMBER = "number",
+            ID = "id",
+            COMMENT = "comment",
 
-    pub const NUMBER: NodeType = NodeType(10, "number");
-    pub const ID: NodeType = NodeType(11, "id");
+            EQ = "=",
+            ADD = "+",
+            SUB = "-",
+            MUL = "*",
+            DIV = "/",
 
-    pub const EQ: NodeType = NodeType(12, "=");
-    pub const ADD: NodeType = NodeType(13, "+");
-    pub const SUB: NodeType = NodeType(14, "-");
-    pub const MUL: NodeType = NodeType(15, "*");
-    pub const DIV: NodeType = NodeType(16, "/");
+            LPAREN = "lparen",
+            RPAREN = "rparen",
+            COMMA = ",",
+        }
 
-    pub const LPAREN: NodeType = NodeType(17, "lparen");
-    pub const RPAREN: NodeType = NodeType(18, "rparen");
+        nodes {
+            LIT_EXPR = "lit_expr",
+            NAME_EXPR = "name_expr",
+            PREFIX_EXPR = "prefix_expr",
+            BIN_EXPR = "bin_expr",
+            CALL_EXPR = "call_expr",
+            ARG_LIST = "arg_list",
+            PAREN_EXPR = "paren_expr",
 
+            ASSIGNMENT = "assignment",
+            EXPR_STMT = "expr_stmt",
 
-    pub const LIT_EXPR: NodeType = NodeType(19, "lit_expr");
-    pub const BIN_EXPR: NodeType = NodeType(20, "bin_expr");
-    pub const CALL_EXPR: NodeType = NodeType(21, "call_expr");
-    pub const PAREN_EXPR: NodeType = NodeType(22, "paren_expr");
+            FILE = "file",
+        }
 
-    pub const ASSIGNMENT: NodeType = NodeType(23, "assignment");
-    pub const EXPR_STMT: NodeType = NodeType(24, "expr_stmt");
+        ast {
+            File(FILE) { stmts: [Stmt] }
+            Assignment(ASSIGNMENT) { name: Name, value: Expr }
+            ExprStmt(EXPR_STMT) { expr: Expr }
+            enum Stmt { Assignment, ExprStmt }
 
-    pub const FILE: NodeType = NodeType(25, "file");
+            Name(ID) {}
+            LitExpr(LIT_EXPR) {}
+            NameExpr(NAME_EXPR) { name: Name }
+            PrefixExpr(PREFIX_EXPR) { expr: Expr }
+            BinExpr(BIN_EXPR) {}
+            CallExpr(CALL_EXPR) { callee: Expr, arg_list: ArgList }
+            ArgList(ARG_LIST) { args: [Expr] }
+            ParenExpr(PAREN_EXPR) { expr: Expr }
+            enum Expr { LitExpr, NameExpr, PrefixExpr, BinExpr, CallExpr, ParenExpr }
+        }
+    }
+}
+
+pub static PYT: syntax::Language = syntax::Language {
+    name: "pyt",
+    node_types: node::NODE_TYPES,
+    file_type: node::FILE,
+    trivia: &[syntax::WHITESPACE, node::COMMENT],
+    comment_owners: &[node::ASSIGNMENT, node::EXPR_STMT],
+    tokenizer: tokenizer::tokenize,
+    parser: parser::parse,
+    reparsers: &[
+        (node::ASSIGNMENT, parser::reparse_stmt),
+        (node::EXPR_STMT, parser::reparse_stmt),
+    ],
+};
+
+pub fn parse(text: String) -> syntax::RstFile {
+    PYT.parse(text)
 }
 
@@ -1,41 +1,444 @@
-use syntax::RstBuilder;
+use syntax::{RstBuilder, CompletedMarker, NodeType};
 
-use node::{ID, EQ, ASSIGNMENT, EXPR_STMT, NUMBER, LIT_EXPR};
+use syntax::ERROR;
+
+use node::{
+    ID, EQ, NUMBER, ADD, SUB, MUL, DIV, LPAREN, RPAREN, COMMA,
+    ASSIGNMENT, EXPR_STMT,
+    LIT_EXPR, NAME_EXPR, PREFIX_EXPR, BIN_EXPR, CALL_EXPR, ARG_LIST, PAREN_EXPR,
+};
 
 pub fn parse(builder: &mut RstBuilder) {
-    loop {
+    while builder.peek().is_some() {
         if !parse_stmt(builder) {
-            return
+            builder.err_and_bump("expected a statement");
         }
-        builder.skip_ws();
+    }
+}
+
+pub fn reparse_stmt(builder: &mut RstBuilder) {
+    // A statement starting with `-` or `(` could be a continuation of
+    // the previous one, which can't be checked from here, so leave it
+    // unparsed to reject the reparse.
+    match builder.peek() {
+        Some(SUB) | Some(LPAREN) => {}
+        _ => { parse_stmt(builder); }
     }
 }
 
 fn parse_stmt(builder: &mut RstBuilder) -> bool {
-    if let Some(ID) = builder.peek() {
-        builder.start(ASSIGNMENT);
-        builder.eat(ID);
-        builder.skip_ws();
-        if let Some(EQ) = builder.peek() {
-            builder.eat(EQ);
+    match builder.peek() {
+        Some(ID) if builder.try_parse(parse_assignment) => true,
+        Some(ID) | Some(NUMBER) | Some(SUB) | Some(LPAREN) => {
+            let m = builder.start(EXPR_STMT);
             parse_expr(builder);
-            builder.finish(ASSIGNMENT);
-            return true;
-        } else {
-            return false;
-//            builder.backtrack(ASSIGNMENT)
+            builder.complete(m);
+            true
         }
+        _ => false
     }
+}
 
-    builder.start(EXPR_STMT);
+/// Fails unless the identifier is followed by `=`.
+fn parse_assignment(builder: &mut RstBuilder) -> bool {
+    let m = builder.start(ASSIGNMENT);
+    builder.eat(ID);
+    if builder.peek() != Some(EQ) {
+        return false;
+    }
+    builder.bump();
     parse_expr(builder);
-    builder.finish(EXPR_STMT);
+    builder.complete(m);
     true
 }
 
+/// Binding power of unary minus.
+const PREFIX_POWER: u8 = 5;
+/// Binding power of calls.
+const POSTFIX_POWER: u8 = 7;
+
+/// Left and right binding powers of a binary operator. The left one is
+/// smaller, so operators of the same precedence associate to the left.
+fn infix_power(op: NodeType) -> Option<(u8, u8)> {
+    match op {
+        ADD | SUB => Some((1, 2)),
+        MUL | DIV => Some((3, 4)),
+        _ => None,
+    }
+}
+
 fn parse_expr(builder: &mut RstBuilder) -> bool {
-    builder.start(LIT_EXPR);
-    builder.eat(NUMBER);
-    builder.finish(LIT_EXPR);
+    parse_expr_bp(builder, 0)
+}
+
+/// Parses an expression whose operators bind at least as tightly as
+/// `min_power`.
+fn parse_expr_bp(builder: &mut RstBuilder, min_power: u8) -> bool {
+    let mut lhs = match parse_atom(builder) {
+        Some(lhs) => lhs,
+        None => return false,
+    };
+    while let Some(op) = builder.peek() {
+        if op == LPAREN && POSTFIX_POWER >= min_power {
+            let m = builder.precede(lhs, CALL_EXPR);
+            parse_args(builder);
+            lhs = builder.complete(m);
+            continue;
+        }
+        let right = match infix_power(op) {
+            Some((left, right)) if left >= min_power => right,
+            _ => break,
+        };
+        let m = builder.precede(lhs, BIN_EXPR);
+        builder.bump();
+        parse_expr_bp(builder, right);
+        lhs = builder.complete(m);
+    }
     true
-}
\ No newline at end of file
+}
+
+fn parse_atom(builder: &mut RstBuilder) -> Option<CompletedMarker> {
+    let result = match builder.peek() {
+        Some(NUMBER) => {
+            let m = builder.start(LIT_EXPR);
+            builder.bump();
+            builder.complete(m)
+        }
+        Some(ID) => {
+            let m = builder.start(NAME_EXPR);
+            builder.bump();
+            builder.complete(m)
+        }
+        Some(SUB) => {
+            let m = builder.start(PREFIX_EXPR);
+            builder.bump();
+            parse_expr_bp(builder, PREFIX_POWER);
+            builder.complete(m)
+        }
+        Some(LPAREN) => {
+            let m = builder.start(PAREN_EXPR);
+            builder.bump();
+            parse_expr(builder);
+            builder.eat(RPAREN);
+            builder.complete(m)
+        }
+        _ => {
+            builder.error("expected an expression");
+            builder.missing(ERROR);
+            return None;
+        }
+    };
+    Some(result)
+}
+
+/// Parses `(a, b)` in `f(a, b)`.
+fn parse_args(builder: &mut RstBuilder) {
+    let m = builder.start(ARG_LIST);
+    builder.bump();
+    loop {
+        match builder.peek() {
+            None | Some(RPAREN) => break,
+            _ => {}
+        }
+        if !parse_expr(builder) {
+            break;
+        }
+        if builder.peek() != Some(COMMA) {
+            break;
+        }
+        builder.bump();
+    }
+    builder.eat(RPAREN);
+    builder.complete(m);
+}
+
+#[cfg(test)]
+fn check_parser(text: &str, expected: &str) {
+    ::syntax::check_parser(&::PYT, text, expected);
+}
+
+#[test]
+fn test_node_types() {
+    ::PYT.check_node_types();
+}
+
+#[test]
+fn test_parser() {
+    check_parser("x = 92 1", r#"
+assignment
+  id "x"
+  whitespace " "
+  = "="
+  whitespace " "
+  lit_expr
+    number "92"
+whitespace " "
+expr_stmt
+  lit_expr
+    number "1"
+    "#);
+}
+
+#[test]
+fn test_empty_file() {
+    check_parser("", "");
+    let file = ::parse("x =".to_owned());
+    let assignment = file.root().children().next().unwrap();
+    let missing = assignment.children().last().unwrap();
+    assert_eq!(missing.ty(), ERROR);
+    assert_eq!(missing.range(), ::syntax::Range::empty_at(3));
+    assert_eq!(assignment.text(), "x =");
+}
+
+#[test]
+fn test_parser_recovery() {
+    check_parser("x 1 = y =", r#"
+expr_stmt
+  name_expr
+    id "x"
+whitespace " "
+expr_stmt
+  lit_expr
+    number "1"
+whitespace " "
+error
+  = "="
+whitespace " "
+assignment
+  id "y"
+  whitespace " "
+  = "="
+  error
+error [4; 5): expected a statement
+error [9; 9): expected an expression
+    "#);
+}
+
+#[test]
+fn test_backtracking() {
+    check_parser("x # c\ny = x", r##"
+expr_stmt
+  name_expr
+    id "x"
+whitespace " "
+assignment
+  comment "# c"
+  whitespace "\n"
+  id "y"
+  whitespace " "
+  = "="
+  whitespace " "
+  name_expr
+    id "x"
+    "##);
+    check_parser("x + 1", r#"
+expr_stmt
+  bin_expr
+    name_expr
+      id "x"
+    whitespace " "
+    + "+"
+    whitespace " "
+    lit_expr
+      number "1"
+    "#);
+}
+
+#[test]
+fn test_binary_exprs() {
+    check_parser("1 + 2 * 3 - x / 4", r#"
+expr_stmt
+  bin_expr
+    bin_expr
+      lit_expr
+        number "1"
+      whitespace " "
+      + "+"
+      whitespace " "
+      bin_expr
+        lit_expr
+          number "2"
+        whitespace " "
+        * "*"
+        whitespace " "
+        lit_expr
+          number "3"
+    whitespace " "
+    - "-"
+    whitespace " "
+    bin_expr
+      name_expr
+        id "x"
+      whitespace " "
+      / "/"
+      whitespace " "
+      lit_expr
+        number "4"
+    "#);
+}
+
+#[test]
+fn test_prefix_exprs() {
+    check_parser("--x * -f(1)", r#"
+expr_stmt
+  bin_expr
+    prefix_expr
+      - "-"
+      prefix_expr
+        - "-"
+        name_expr
+          id "x"
+    whitespace " "
+    * "*"
+    whitespace " "
+    prefix_expr
+      - "-"
+      call_expr
+        name_expr
+          id "f"
+        arg_list
+          lparen "("
+          lit_expr
+            number "1"
+          rparen ")"
+    "#);
+}
+
+#[test]
+fn test_paren_exprs() {
+    check_parser("(1 + 2) * (x)", r#"
+expr_stmt
+  bin_expr
+    paren_expr
+      lparen "("
+      bin_expr
+        lit_expr
+          number "1"
+        whitespace " "
+        + "+"
+        whitespace " "
+        lit_expr
+          number "2"
+      rparen ")"
+    whitespace " "
+    * "*"
+    whitespace " "
+    paren_expr
+      lparen "("
+      name_expr
+        id "x"
+      rparen ")"
+    "#);
+}
+
+#[test]
+fn test_call_exprs() {
+    check_parser("print(input(), x + 1) f()(1,)", r#"
+expr_stmt
+  call_expr
+    name_expr
+      id "print"
+    arg_list
+      lparen "("
+      call_expr
+        name_expr
+          id "input"
+        arg_list
+          lparen "("
+          rparen ")"
+      , ","
+      whitespace " "
+      bin_expr
+        name_expr
+          id "x"
+        whitespace " "
+        + "+"
+        whitespace " "
+        lit_expr
+          number "1"
+      rparen ")"
+whitespace " "
+expr_stmt
+  call_expr
+    call_expr
+      name_expr
+        id "f"
+      arg_list
+        lparen "("
+        rparen ")"
+    arg_list
+      lparen "("
+      lit_expr
+        number "1"
+      , ","
+      rparen ")"
+    "#);
+}
+
+#[test]
+fn test_expr_recovery() {
+    check_parser("x = (1 + ) * f(1 2", r#"
+assignment
+  id "x"
+  whitespace " "
+  = "="
+  whitespace " "
+  bin_expr
+    paren_expr
+      lparen "("
+      bin_expr
+        lit_expr
+          number "1"
+        whitespace " "
+        + "+"
+        whitespace " "
+        error
+      rparen ")"
+    whitespace " "
+    * "*"
+    whitespace " "
+    call_expr
+      name_expr
+        id "f"
+      arg_list
+        lparen "("
+        lit_expr
+          number "1"
+whitespace " "
+expr_stmt
+  lit_expr
+    number "2"
+error [9; 9): expected an expression
+error [17; 17): expected rparen
+    "#);
+}
+
+#[test]
+fn test_comments() {
+    check_parser("# x\nx = 1 # one\n\n# unattached\n\n92", r##"
+assignment
+  comment "# x"
+  whitespace "\n"
+  id "x"
+  whitespace " "
+  = "="
+  whitespace " "
+  lit_expr
+    number "1"
+whitespace " "
+comment "# one"
+whitespace "\n\n"
+comment "# unattached"
+whitespace "\n\n"
+expr_stmt
+  lit_expr
+    number "92"
+"##);
+}
+
+#[test]
+fn test_incremental_reparse() {
+    let text = "x = 1 + 2 * (3 - y)\nprint(f(x, 92))\nz = -x\ny = 92 2";
+    let fragments = [" x = 1 ", " y", " 92", " + 2", " * (3)", " f(1, 2)", " # c\n", "\n", " ", "=", "-", "(", ")", ",", "$"];
+    ::syntax::check_incremental(&::PYT, text, &fragments, 2000, 500);
+}
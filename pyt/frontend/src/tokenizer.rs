@@ -1,29 +1,42 @@
-use node::{NUMBER, ID, EQ, ADD, SUB, MUL, DIV, LPAREN, RPAREN};
-use syntax::{TokenBuilder, NodeType, WHITESPACE};
+use node::{NUMBER, ID, COMMENT, EQ, ADD, SUB, MUL, DIV, LPAREN, RPAREN, COMMA};
+use syntax::{TokenBuilder, Lexer, WHITESPACE};
 
 
 pub fn tokenize(builder: &mut TokenBuilder) {
-    let text_tokens = [
-        (ADD, '+'), (SUB, '-'), (MUL, '*'), (DIV, '/'),
-        (EQ, '='), (LPAREN, '('), (RPAREN, ')')
-    ];
+    thread_local! {
+        static LEXER: Lexer = Lexer::new(&[
+            (ADD, r"\+"), (SUB, r"-"), (MUL, r"\*"), (DIV, r"/"),
+            (EQ, r"="), (LPAREN, r"\("), (RPAREN, r"\)"), (COMMA, r","),
+            (ID, r"[\p{Alphabetic}_][\p{Alphabetic}\d_]*"),
+            (WHITESPACE, r"\p{White_Space}+"),
+            (COMMENT, r"#[^\n]*"),
+            (NUMBER, r"\d+"),
+        ]);
+    }
+    LEXER.with(|lexer| lexer.next_token(builder))
+}
 
-    let pred_tokens: &[(NodeType, &Fn(char) -> bool)] = &[
-        (ID, &char::is_alphabetic),
-        (WHITESPACE, &char::is_whitespace),
-        (NUMBER, &|c| c.is_digit(10)),
-    ];
+#[test]
+fn test_tokenizer() {
+    ::syntax::check_tokenizer(&::PYT, "foo_1 = 2*(x1-3)", r#"
+id "foo_1"
+whitespace " "
+= "="
+whitespace " "
+number "2"
+* "*"
+lparen "("
+id "x1"
+- "-"
+number "3"
+rparen ")"
+"#);
 
-    loop {
-        if builder.try_text_token(&text_tokens) || builder.try_pred_token(&pred_tokens) {
-            continue
-        }
-        match builder.peek() {
-            Some(_) => {
-                builder.bump();
-                builder.error();
-            }
-            None => break
-        };
-    }
+    ::syntax::check_tokenizer(&::PYT, "прив_2\u{3000}= 1", r#"
+id "прив_2"
+whitespace "\u{3000}"
+= "="
+whitespace " "
+number "1"
+"#);
 }
@@ -1,17 +1,51 @@
+extern crate asm;
+
 use std::process::Command;
-use std::env;
+use std::{env, fs};
 use std::path::Path;
 
+use asm::{Function, Inst, Operand, Program, Reg};
+
 fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
 
-    Command::new("nasm").args(&["src/syscall.asm", "-felf64", "-o"])
-        .arg(&format!("{}/syscall.o", out_dir))
-        .status().unwrap();
+    fs::write(Path::new(&out_dir).join("syscall.o"), syscalls().to_object()).unwrap();
     Command::new("ar").args(&["crus", "libsyscall.a", "syscall.o"])
         .current_dir(&Path::new(&out_dir))
         .status().unwrap();
 
     println!("cargo:rustc-link-search=native={}", out_dir);
     println!("cargo:rustc-link-lib=static=syscall");
-}
\ No newline at end of file
+}
+
+/// `syscallN(number, arg1, ..., argN)`: moves the arguments from the
+/// System V calling convention to the one of the kernel.
+fn syscalls() -> Program {
+    let mov = |dst, src| Inst::Mov(Operand::Reg(dst), Operand::Reg(src));
+    Program {
+        externs: Vec::new(),
+        functions: vec![
+            Function {
+                name: "syscall1".to_owned(),
+                body: vec![
+                    mov(Reg::Rax, Reg::Rdi),
+                    mov(Reg::Rdi, Reg::Rsi),
+                    Inst::Syscall,
+                    Inst::Ret,
+                ],
+            },
+            Function {
+                name: "syscall3".to_owned(),
+                body: vec![
+                    mov(Reg::Rax, Reg::Rdi),
+                    mov(Reg::Rdi, Reg::Rsi),
+                    mov(Reg::Rsi, Reg::Rdx),
+                    mov(Reg::Rdx, Reg::Rcx),
+                    Inst::Syscall,
+                    Inst::Ret,
+                ],
+            },
+        ],
+        data: Vec::new(),
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::{HashMap, HashSet};
+
+use syntax::{RstFile, Node, NodeType, Diagnostics};
+use frontend::node::{
+    NUMBER, ID, ADD, SUB, MUL, DIV,
+    ASSIGNMENT, EXPR_STMT,
+    LIT_EXPR, NAME_EXPR, PREFIX_EXPR, BIN_EXPR, CALL_EXPR, ARG_LIST, PAREN_EXPR,
+};
+
+use asm::{Function, Inst, Operand, Program, Reg};
+
+/// Functions of the runtime which can be called from `pyt`: the name,
+/// the number of arguments and whether the function returns a value.
+const FUNCTIONS: &[(&str, usize, bool)] = &[
+    ("input", 0, true),
+    ("print", 1, false),
+];
+
+/// Registers for passing arguments, as in the System V calling convention.
+const ARGUMENTS: &[Reg] = &[Reg::Rdi];
+
+/// Compiles a file without syntax errors into a `_start` function, which
+/// runs the statements one by one and calls `exit`.
+///
+/// Values are unsigned 64 bit integers. The value of an expression is
+/// computed into `rax`. Variables, and temporaries for the left operands
+/// of binary expressions, live in the stack frame.
+pub fn generate(file: &RstFile) -> Result<Program, Diagnostics> {
+    let mut vars = HashMap::new();
+    for stmt in file.root().children().filter(|stmt| stmt.ty() == ASSIGNMENT) {
+        let n_vars = vars.len();
+        vars.entry(name(stmt)).or_insert(n_vars);
+    }
+    let mut codegen = Codegen {
+        vars: vars,
+        defined: HashSet::new(),
+        n_temps: 0,
+        max_temps: 0,
+        body: Vec::new(),
+        diagnostics: Diagnostics::new(),
+    };
+    for stmt in file.root().children() {
+        codegen.stmt(stmt);
+    }
+    codegen.emit(Inst::Call("exit".to_owned()));
+    if codegen.diagnostics.has_errors() {
+        return Err(codegen.diagnostics);
+    }
+
+    // Keep `rsp` 16 bytes aligned, as it is at the start.
+    let n_slots = codegen.vars.len() + codegen.max_temps;
+    let frame_size = 8 * (n_slots + n_slots % 2) as u64;
+    let mut body = Vec::new();
+    if frame_size > 0 {
+        body.push(Inst::Mov(Operand::Reg(Reg::Rbp), Operand::Reg(Reg::Rsp)));
+        body.push(Inst::Sub(Reg::Rsp, Operand::Imm(frame_size)));
+    }
+    body.extend(codegen.body);
+    let mut externs: Vec<String> = FUNCTIONS.iter().map(|&(name, _, _)| name.to_owned()).collect();
+    externs.push("exit".to_owned());
+    Ok(Program {
+        externs: externs,
+        functions: vec![Function { name: "_start".to_owned(), body: body }],
+        data: Vec::new(),
+    })
+}
+
+struct Codegen<'f> {
+    /// Stack slots of variables.
+    vars: HashMap<&'f str, usize>,
+    /// Variables which are assigned before the current statement.
+    defined: HashSet<&'f str>,
+    n_temps: usize,
+    max_temps: usize,
+    body: Vec<Inst>,
+    diagnostics: Diagnostics,
+}
+
+impl<'f> Codegen<'f> {
+    fn stmt(&mut self, stmt: Node<'f>) {
+        match stmt.ty() {
+            ASSIGNMENT => {
+                self.expr(expr(stmt));
+                let name = name(stmt);
+                let slot = self.var(self.vars[name]);
+                self.emit(Inst::Mov(slot, Operand::Reg(Reg::Rax)));
+                self.defined.insert(name);
+            }
+            EXPR_STMT => {
+                let expr = expr(stmt);
+                if expr.ty() == CALL_EXPR {
+                    self.call(expr, false);
+                } else {
+                    self.expr(expr);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn expr(&mut self, expr: Node<'f>) {
+        match expr.ty() {
+            LIT_EXPR => match token_text(expr, NUMBER).parse::<u64>() {
+                Ok(value) => self.emit(Inst::Mov(Operand::Reg(Reg::Rax), Operand::Imm(value))),
+                Err(_) => self.diagnostics.error(expr.text_range(), "number is too large"),
+            },
+            NAME_EXPR => {
+                let name = token_text(expr, ID);
+                if !self.defined.contains(name) {
+                    self.diagnostics.error(expr.text_range(), format!("undefined variable `{}`", name));
+                    return;
+                }
+                let slot = self.var(self.vars[name]);
+                self.emit(Inst::Mov(Operand::Reg(Reg::Rax), slot));
+            }
+            PAREN_EXPR => self.expr(self::expr(expr)),
+            PREFIX_EXPR => {
+                self.expr(self::expr(expr));
+                self.emit(Inst::Neg(Reg::Rax));
+            }
+            BIN_EXPR => {
+                let operands = exprs(expr);
+                let temp = self.temp();
+                self.expr(operands[0]);
+                self.emit(Inst::Mov(temp, Operand::Reg(Reg::Rax)));
+                self.expr(operands[1]);
+                self.emit(Inst::Mov(Operand::Reg(Reg::Rcx), Operand::Reg(Reg::Rax)));
+                self.emit(Inst::Mov(Operand::Reg(Reg::Rax), temp));
+                self.n_temps -= 1;
+                let rcx = Operand::Reg(Reg::Rcx);
+                match op(expr) {
+                    ADD => self.emit(Inst::Add(Reg::Rax, rcx)),
+                    SUB => self.emit(Inst::Sub(Reg::Rax, rcx)),
+                    MUL => self.emit(Inst::Imul(Reg::Rax, rcx)),
+                    DIV => {
+                        self.emit(Inst::Mov(Operand::Reg(Reg::Rdx), Operand::Imm(0)));
+                        self.emit(Inst::Div(Reg::Rcx));
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            CALL_EXPR => self.call(expr, true),
+            _ => unreachable!(),
+        }
+    }
+
+    fn call(&mut self, call: Node<'f>, needs_value: bool) {
+        let callee = self::expr(call);
+        if callee.ty() != NAME_EXPR {
+            self.diagnostics.error(callee.text_range(), "expected a function name");
+            return;
+        }
+        let callee_name = token_text(callee, ID);
+        let (name, arity, returns) = match FUNCTIONS.iter().find(|&&(name, _, _)| name == callee_name) {
+            Some(&function) => function,
+            None => {
+                self.diagnostics.error(callee.text_range(), format!("unknown function `{}`", callee_name));
+                return;
+            }
+        };
+        let arg_list = call.children().find(|node| node.ty() == ARG_LIST).unwrap();
+        let args = exprs(arg_list);
+        if args.len() != arity {
+            let message = format!("`{}` takes {}, got {}", name, arguments(arity), args.len());
+            self.diagnostics.error(arg_list.text_range(), message);
+            return;
+        }
+        if needs_value && !returns {
+            self.diagnostics.error(call.text_range(), format!("`{}` doesn't return a value", name));
+            return;
+        }
+        assert!(arity <= ARGUMENTS.len());
+        if let Some(&arg) = args.first() {
+            self.expr(arg);
+            self.emit(Inst::Mov(Operand::Reg(ARGUMENTS[0]), Operand::Reg(Reg::Rax)));
+        }
+        self.emit(Inst::Call(name.to_owned()));
+    }
+
+    fn var(&self, slot: usize) -> Operand {
+        Operand::Mem(Reg::Rbp, -8 * (slot as i32 + 1))
+    }
+
+    /// Allocates a slot for a temporary value, which must be freed by
+    /// decrementing `n_temps`.
+    fn temp(&mut self) -> Operand {
+        let slot = self.vars.len() + self.n_temps;
+        self.n_temps += 1;
+        self.max_temps = self.max_temps.max(self.n_temps);
+        self.var(slot)
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.body.push(inst)
+    }
+}
+
+fn arguments(n: usize) -> String {
+    format!("{} argument{}", n, if n == 1 { "" } else { "s" })
+}
+
+fn is_expr(ty: NodeType) -> bool {
+    [LIT_EXPR, NAME_EXPR, PREFIX_EXPR, BIN_EXPR, CALL_EXPR, PAREN_EXPR].contains(&ty)
+}
+
+fn exprs(node: Node) -> Vec<Node> {
+    node.children().filter(|child| is_expr(child.ty())).collect()
+}
+
+fn expr(node: Node) -> Node {
+    exprs(node)[0]
+}
+
+fn name(assignment: Node) -> &str {
+    token_text(assignment, ID)
+}
+
+/// The text of the `ty` token child, which unlike the text of the node
+/// itself never includes comments.
+fn token_text(node: Node, ty: NodeType) -> &str {
+    node.children().find(|child| child.ty() == ty).unwrap().text()
+}
+
+fn op(bin_expr: Node) -> NodeType {
+    bin_expr.children().map(|child| child.ty())
+        .find(|&ty| [ADD, SUB, MUL, DIV].contains(&ty))
+        .unwrap()
+}
+
+#[cfg(test)]
+fn check_codegen(text: &str, expected: &str) {
+    let file = ::frontend::parse(text.to_owned());
+    assert!(!file.diagnostics().has_errors(), "{}", file.diagnostics().render("<test>", file.text()));
+    let actual = match generate(&file) {
+        Ok(program) => program.to_string(),
+        Err(diagnostics) => diagnostics.render("<test>", file.text()),
+    };
+    assert!(actual.trim() == expected.trim(), "\nInput:\n{}\n\nOutput:\n{}\n\nExpected:\n{}\n", text, actual, expected);
+}
+
+#[test]
+fn test_codegen() {
+    check_codegen("x = -input()\nprint(x + input())", r#"
+global _start
+extern input
+extern print
+extern exit
+
+
+section .text
+_start:
+    mov rbp, rsp
+    sub rsp, 16
+    call input
+    neg rax
+    mov qword [rbp - 8], rax
+    mov rax, qword [rbp - 8]
+    mov qword [rbp - 16], rax
+    call input
+    mov rcx, rax
+    mov rax, qword [rbp - 16]
+    add rax, rcx
+    mov rdi, rax
+    call print
+    call exit
+"#);
+}
+
+#[test]
+fn test_codegen_arithmetic() {
+    check_codegen("print(1 * 2 / (3 - 4))", r#"
+global _start
+extern input
+extern print
+extern exit
+
+
+section .text
+_start:
+    mov rbp, rsp
+    sub rsp, 16
+    mov rax, 1
+    mov qword [rbp - 16], rax
+    mov rax, 2
+    mov rcx, rax
+    mov rax, qword [rbp - 16]
+    imul rax, rcx
+    mov qword [rbp - 8], rax
+    mov rax, 3
+    mov qword [rbp - 16], rax
+    mov rax, 4
+    mov rcx, rax
+    mov rax, qword [rbp - 16]
+    sub rax, rcx
+    mov rcx, rax
+    mov rax, qword [rbp - 8]
+    mov rdx, 0
+    div rcx
+    mov rdi, rax
+    call print
+    call exit
+"#);
+}
+
+#[test]
+fn test_codegen_errors() {
+    check_codegen("x = y\nprint()\nz = print(1)\nfoo(1)\ninput()(1)", r#"
+error: undefined variable `y`
+ --> <test>:1:5
+  |
+1 | x = y
+  |     ^
+
+error: `print` takes 1 argument, got 0
+ --> <test>:2:6
+  |
+2 | print()
+  |      ^^
+
+error: `print` doesn't return a value
+ --> <test>:3:5
+  |
+3 | z = print(1)
+  |     ^^^^^^^^
+
+error: unknown function `foo`
+ --> <test>:4:1
+  |
+4 | foo(1)
+  | ^^^
+
+error: expected a function name
+ --> <test>:5:1
+  |
+5 | input()(1)
+  | ^^^^^^^
+"#);
+}
+
+#[test]
+fn test_codegen_comments() {
+    for &text in ["x = 5\nprint(\n# the value\nx)", "x = # c\n5\nprint(x)", "# c\nprint(# c\n1)"].iter() {
+        let file = ::frontend::parse(text.to_owned());
+        if let Err(diagnostics) = generate(&file) {
+            panic!("{}", diagnostics.render("<test>", file.text()));
+        }
+    }
+}
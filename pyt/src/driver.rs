@@ -0,0 +1,239 @@
+use std::{env, fs, io, process};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use syntax::{Diagnostic, Range, RstFile};
+
+use asm::Program;
+use {codegen, interpreter};
+
+/// The stage at which `pyt build` stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Rst,
+    Asm,
+    Obj,
+    Exe,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BuildOptions {
+    pub input: PathBuf,
+    /// Defaults to the input with an extension for the emitted stage.
+    pub output: Option<PathBuf>,
+    pub emit: Emit,
+    /// The static library of the `runtime` crate. Defaults to the one
+    /// next to the `pyt` binary, which is where cargo puts it.
+    pub runtime: Option<PathBuf>,
+}
+
+pub const USAGE: &str = "\
+usage: pyt build <file> [-o <output>] [--emit=tokens|rst|asm|obj] [--runtime=<libruntime.a>]
+       pyt run <file>
+";
+
+/// Parses the arguments following `pyt build`.
+pub fn parse_build_args(args: &[String]) -> Result<BuildOptions, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut emit = Emit::Exe;
+    let mut runtime = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return Err("`-o` requires a path".to_owned()),
+            }
+        } else if let Some(kind) = arg.strip_prefix("--emit=") {
+            emit = match kind {
+                "tokens" => Emit::Tokens,
+                "rst" => Emit::Rst,
+                "asm" => Emit::Asm,
+                "obj" => Emit::Obj,
+                kind => return Err(format!("unknown kind of output `{}`", kind)),
+            };
+        } else if let Some(path) = arg.strip_prefix("--runtime=") {
+            runtime = Some(PathBuf::from(path));
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option `{}`", arg));
+        } else if input.is_some() {
+            return Err(format!("unexpected argument `{}`", arg));
+        } else {
+            input = Some(PathBuf::from(arg));
+        }
+    }
+    let input = input.ok_or("no input file")?;
+    Ok(BuildOptions { input: input, output: output, emit: emit, runtime: runtime })
+}
+
+/// Runs the compiler up to `options.emit`. Returns rendered diagnostics
+/// on failure.
+pub fn build(options: &BuildOptions) -> Result<(), String> {
+    let file_name = options.input.display().to_string();
+    let text = read(&options.input)?;
+    let output = match options.output {
+        Some(ref output) => output.clone(),
+        None => options.input.with_extension(match options.emit {
+            Emit::Tokens => "tokens",
+            Emit::Rst => "rst",
+            Emit::Asm => "asm",
+            Emit::Obj => "o",
+            Emit::Exe => "",
+        }),
+    };
+
+    if options.emit == Emit::Tokens {
+        return write(&output, frontend::PYT.tokenize(text).dump());
+    }
+    let rst = frontend::parse(text);
+    if options.emit == Emit::Rst {
+        return write(&output, rst.dump());
+    }
+    let program = compile(&file_name, &rst)?;
+    if options.emit == Emit::Asm {
+        return write(&output, program.to_string());
+    }
+
+    if options.emit == Emit::Obj {
+        return write(&output, program.to_object());
+    }
+
+    let runtime = match options.runtime {
+        Some(ref runtime) => runtime.clone(),
+        None => default_runtime()?,
+    };
+    let dir = env::temp_dir().join(format!("pyt-{}", process::id()));
+    fs::create_dir_all(&dir)
+        .map_err(|e| error(format!("can't create {}: {}", dir.display(), e)))?;
+    let result = link(&dir, program.to_object(), &output, &runtime);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// Interprets the program with the standard input and output, after the
+/// same checks as `build`.
+pub fn interpret(input: &Path) -> Result<(), String> {
+    let file_name = input.display().to_string();
+    let rst = frontend::parse(read(input)?);
+    compile(&file_name, &rst)?;
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    interpreter::run(&rst, &mut stdin.lock(), &mut stdout.lock())
+        .map_err(|d| d.render(&file_name, rst.text()))
+}
+
+fn compile(file_name: &str, rst: &RstFile) -> Result<Program, String> {
+    if rst.diagnostics().has_errors() {
+        return Err(rst.diagnostics().render(file_name, rst.text()));
+    }
+    codegen::generate(rst)
+        .map_err(|diagnostics| diagnostics.render(file_name, rst.text()))
+}
+
+/// Links the object file with the `runtime` into an executable.
+fn link(dir: &Path, object: Vec<u8>, output: &Path, runtime: &Path) -> Result<(), String> {
+    let obj_path = dir.join("main.o");
+    write(&obj_path, object)?;
+    run(Command::new("ld").arg(&obj_path).arg(runtime).arg("-o").arg(output))
+}
+
+fn default_runtime() -> Result<PathBuf, String> {
+    let exe = env::current_exe()
+        .map_err(|e| error(format!("can't find the runtime library: {}", e)))?;
+    let runtime = exe.with_file_name("libruntime.a");
+    if !runtime.exists() {
+        let d = diagnostic(format!("can't find the runtime library at {}", runtime.display()))
+            .with_note("build it with `cargo build --package runtime --release` or pass `--runtime=<path>`");
+        return Err(d.render_message());
+    }
+    Ok(runtime)
+}
+
+/// Runs an external tool, reporting its output if it fails.
+fn run(command: &mut Command) -> Result<(), String> {
+    let tool = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|e| {
+        diagnostic(format!("can't run `{}`: {}", tool, e))
+            .with_note(format!("is `{}` installed?", tool))
+            .render_message()
+    })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut d = diagnostic(format!("`{}` failed with {}", tool, output.status));
+    for line in String::from_utf8_lossy(&output.stderr).lines().filter(|line| !line.is_empty()) {
+        d = d.with_note(line);
+    }
+    Err(d.render_message())
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path)
+        .map_err(|e| error(format!("can't read {}: {}", path.display(), e)))
+}
+
+fn write<C: AsRef<[u8]>>(path: &Path, contents: C) -> Result<(), String> {
+    fs::write(path, contents)
+        .map_err(|e| error(format!("can't write {}: {}", path.display(), e)))
+}
+
+fn error(message: String) -> String {
+    diagnostic(message).render_message()
+}
+
+/// A diagnostic which is not tied to the source.
+fn diagnostic(message: String) -> Diagnostic {
+    Diagnostic::error(Range::empty_at(0), message)
+}
+
+#[cfg(test)]
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|&arg| arg.to_owned()).collect()
+}
+
+#[test]
+fn test_parse_build_args() {
+    assert_eq!(parse_build_args(&args(&["prog.pyt", "-o", "out", "--emit=asm"])), Ok(BuildOptions {
+        input: PathBuf::from("prog.pyt"),
+        output: Some(PathBuf::from("out")),
+        emit: Emit::Asm,
+        runtime: None,
+    }));
+    assert_eq!(parse_build_args(&args(&["--runtime=lib.a", "prog.pyt"])).map(|o| o.runtime),
+               Ok(Some(PathBuf::from("lib.a"))));
+    assert_eq!(parse_build_args(&args(&[])), Err("no input file".to_owned()));
+    assert_eq!(parse_build_args(&args(&["prog.pyt", "--emit=exe"])),
+               Err("unknown kind of output `exe`".to_owned()));
+    assert_eq!(parse_build_args(&args(&["prog.pyt", "-o"])), Err("`-o` requires a path".to_owned()));
+}
+
+#[test]
+fn test_build_stages() {
+    let dir = env::temp_dir().join(format!("pyt-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("prog.pyt");
+    fs::write(&input, "x = 1\nprint(x)\n").unwrap();
+    for &(emit, extension, expected) in [
+        (Emit::Tokens, "tokens", "id \"x\"\n"),
+        (Emit::Rst, "rst", "assignment\n"),
+        (Emit::Asm, "asm", "global _start\n"),
+    ].iter() {
+        let options = BuildOptions { input: input.clone(), output: None, emit: emit, runtime: None };
+        build(&options).unwrap();
+        let actual = fs::read_to_string(input.with_extension(extension)).unwrap();
+        assert!(actual.starts_with(expected), "{:?}:\n{}", emit, actual);
+    }
+
+    fs::write(&input, "print(y)\n").unwrap();
+    let options = BuildOptions { input: input.clone(), output: None, emit: Emit::Asm, runtime: None };
+    let error = build(&options).unwrap_err();
+    assert!(error.starts_with("error: undefined variable `y`"), "{}", error);
+
+    fs::write(&input, "print(input())\n").unwrap();
+    let options = BuildOptions { input: input.clone(), output: None, emit: Emit::Obj, runtime: None };
+    build(&options).unwrap();
+    assert!(fs::read(input.with_extension("o")).unwrap().starts_with(b"\x7FELF"));
+    fs::remove_dir_all(&dir).unwrap();
+}
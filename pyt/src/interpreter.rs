@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use syntax::{RstFile, Node, Diagnostic};
+use frontend::ast::{AstElement, File, Stmt, Expr, BinOp, CallExpr};
+
+/// Runs a file which `codegen::generate` accepts, with the semantics of
+/// the compiled code: values are wrapping `u64`s, `input()` reads the
+/// next decimal number and `print(x)` writes one, without a separator.
+///
+/// Division by zero and running out of input are reported as errors,
+/// while the compiled code crashes or hangs. Files which the compiler
+/// rejects run up to the first error.
+pub fn run(file: &RstFile, input: &mut BufRead, output: &mut Write) -> Result<(), Diagnostic> {
+    let mut interpreter = Interpreter {
+        vars: HashMap::new(),
+        input: input,
+        output: output,
+    };
+    for stmt in File::from_node(file.root()).stmts() {
+        interpreter.stmt(stmt)?;
+    }
+    Ok(())
+}
+
+struct Interpreter<'f, 'io> {
+    vars: HashMap<&'f str, u64>,
+    input: &'io mut BufRead,
+    output: &'io mut Write,
+}
+
+impl<'f, 'io> Interpreter<'f, 'io> {
+    fn stmt(&mut self, stmt: Stmt<'f>) -> Result<(), Diagnostic> {
+        match stmt {
+            Stmt::Assignment(assignment) => {
+                let node = assignment.node();
+                let value = self.expr(expected(assignment.value(), node, "an expression")?)?;
+                let name = expected(assignment.name(), node, "a name")?;
+                self.vars.insert(name.text(), value);
+            }
+            Stmt::ExprStmt(stmt) => {
+                self.expr(expected(stmt.expr(), stmt.node(), "an expression")?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: Expr<'f>) -> Result<u64, Diagnostic> {
+        let node = expr.node();
+        let value = match expr {
+            Expr::LitExpr(lit) => match lit.value() {
+                Some(value) => value,
+                None => return Err(error(node, "number is too large")),
+            },
+            Expr::NameExpr(name_expr) => {
+                let name = expected(name_expr.name(), node, "a name")?.text();
+                match self.vars.get(name) {
+                    Some(&value) => value,
+                    None => return Err(error(node, format!("undefined variable `{}`", name))),
+                }
+            }
+            Expr::ParenExpr(paren_expr) => self.expr(expected(paren_expr.expr(), node, "an expression")?)?,
+            Expr::PrefixExpr(prefix_expr) => {
+                self.expr(expected(prefix_expr.expr(), node, "an expression")?)?.wrapping_neg()
+            }
+            Expr::BinExpr(bin_expr) => {
+                let lhs = self.expr(expected(bin_expr.lhs(), node, "an expression")?)?;
+                let op = expected(bin_expr.op(), node, "an operator")?;
+                let rhs = self.expr(expected(bin_expr.rhs(), node, "an expression")?)?;
+                match op {
+                    BinOp::Add => lhs.wrapping_add(rhs),
+                    BinOp::Sub => lhs.wrapping_sub(rhs),
+                    BinOp::Mul => lhs.wrapping_mul(rhs),
+                    BinOp::Div => match lhs.checked_div(rhs) {
+                        Some(value) => value,
+                        None => return Err(error(node, "division by zero")),
+                    },
+                }
+            }
+            Expr::CallExpr(call) => self.call(call)?,
+        };
+        Ok(value)
+    }
+
+    /// Returns `0` for `print`, whose value is never used.
+    fn call(&mut self, call: CallExpr<'f>) -> Result<u64, Diagnostic> {
+        let node = call.node();
+        let callee = match call.callee() {
+            Some(Expr::NameExpr(name_expr)) => expected(name_expr.name(), node, "a name")?,
+            Some(callee) => return Err(error(callee.node(), "expected a function name")),
+            None => return Err(error(node, "expected a function name")),
+        };
+        let mut args = Vec::new();
+        for arg in call.args() {
+            args.push(self.expr(arg)?);
+        }
+        match (callee.text(), &args[..]) {
+            ("input", []) => self.input(node),
+            ("print", &[value]) => {
+                write!(self.output, "{}", value)
+                    .and_then(|()| self.output.flush())
+                    .map_err(|e| io_error(node, "can't write the output", e))?;
+                Ok(0)
+            }
+            ("input", _) | ("print", _) => {
+                Err(error(node, format!("wrong number of arguments to `{}`", callee.text())))
+            }
+            (name, _) => Err(error(callee.node(), format!("unknown function `{}`", name))),
+        }
+    }
+
+    /// Like `runtime::input`, skips everything up to a digit and consumes
+    /// one byte after the number.
+    fn input(&mut self, call: Node) -> Result<u64, Diagnostic> {
+        let mut result: Option<u64> = None;
+        while let Some(byte) = self.next_byte().map_err(|e| io_error(call, "can't read the input", e))? {
+            let digit = if byte.is_ascii_digit() { Some((byte - b'0') as u64) } else { None };
+            match (result, digit) {
+                (_, Some(digit)) => result = Some(result.unwrap_or(0).wrapping_mul(10).wrapping_add(digit)),
+                (Some(_), None) => break,
+                (None, None) => {}
+            }
+        }
+        result.ok_or_else(|| error(call, "unexpected end of input"))
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = match self.input.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => return Ok(None),
+        };
+        self.input.consume(1);
+        Ok(Some(byte))
+    }
+}
+
+fn expected<T>(element: Option<T>, node: Node, what: &str) -> Result<T, Diagnostic> {
+    element.ok_or_else(|| error(node, format!("expected {}", what)))
+}
+
+fn error<M: Into<String>>(node: Node, message: M) -> Diagnostic {
+    Diagnostic::error(node.text_range(), message)
+}
+
+fn io_error(call: Node, message: &str, e: io::Error) -> Diagnostic {
+    error(call, format!("{}: {}", message, e))
+}
+
+#[cfg(test)]
+fn check_run(text: &str, input: &str, expected: &str) {
+    let file = ::frontend::parse(text.to_owned());
+    assert!(!file.diagnostics().has_errors(), "{}", file.diagnostics().render("<test>", file.text()));
+    let mut output = Vec::new();
+    let result = run(&file, &mut input.as_bytes(), &mut output);
+    let mut actual = String::from_utf8(output).unwrap();
+    if let Err(diagnostic) = result {
+        actual.push_str(&diagnostic.render("<test>", file.text()));
+    }
+    assert!(actual.trim() == expected.trim(), "\nInput:\n{}\n\nOutput:\n{}\n\nExpected:\n{}\n", text, actual, expected);
+}
+
+#[test]
+fn test_run() {
+    check_run("x = -input()\nprint(x + input())", "92 100\n", "8");
+    check_run("print(1 * 2 / (3 - 4))\nprint(0)", "", "00");
+    check_run("x = 7\nprint(x)\nx = x * x\nprint(x / 2)", "", "724");
+    check_run("print(-1)", "", "18446744073709551615");
+    check_run("print(4294967296 * 4294967296 + 18446744073709551615 * 2)", "", "18446744073709551614");
+    check_run("print(input() - input())", "  a12b-3", "9");
+}
+
+#[test]
+fn test_run_errors() {
+    check_run("print(1)\nx = 1 / (input() - 1)", "1", r#"
+1error: division by zero
+ --> <test>:2:5
+  |
+2 | x = 1 / (input() - 1)
+  |     ^^^^^^^^^^^^^^^^^
+"#);
+    check_run("print(input())\nprint(input())", "42", r#"
+42error: unexpected end of input
+ --> <test>:2:7
+  |
+2 | print(input())
+  |       ^^^^^^^
+"#);
+}
+
+#[test]
+fn test_run_comments() {
+    check_run("x = # c\n5\nprint(\n# the value\nx)", "", "5");
+}
+
+#[test]
+fn test_run_rejected() {
+    let check = |text: &str, expected: &str| {
+        let file = ::frontend::parse(text.to_owned());
+        let mut output = Vec::new();
+        let diagnostic = run(&file, &mut "".as_bytes(), &mut output).unwrap_err();
+        assert_eq!(diagnostic.message, expected);
+    };
+    check("print(99999999999999999999)", "number is too large");
+    check("print(x)", "undefined variable `x`");
+    check("foo()", "unknown function `foo`");
+    check("print()", "wrong number of arguments to `print`");
+    check("x = (1 +)", "expected an expression");
+}
@@ -1,3 +1,25 @@
+extern crate syntax;
+extern crate frontend;
+extern crate asm;
+
+mod codegen;
+mod driver;
+mod interpreter;
+
+use std::path::Path;
+
 fn main() {
-    println!("Hello, world!");
-}
\ No newline at end of file
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|arg| &arg[..]) {
+        Some("build") => match driver::parse_build_args(&args[1..]) {
+            Ok(options) => driver::build(&options),
+            Err(message) => Err(format!("error: {}\n{}", message, driver::USAGE)),
+        },
+        Some("run") if args.len() == 2 => driver::interpret(Path::new(&args[1])),
+        _ => Err(driver::USAGE.to_owned()),
+    };
+    if let Err(message) = result {
+        eprint!("{}", message);
+        std::process::exit(1);
+    }
+}
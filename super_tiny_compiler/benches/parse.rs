@@ -0,0 +1,71 @@
+//! Compares the peak memory and the time of parsing a large file with
+//! and without materializing the tokens first. Run with `cargo bench -p
+//! super_tiny_compiler`.
+//!
+//! This is a separate binary because it replaces the global allocator.
+//!
+//! On the 4 MB file below, tokenizing lazily lowers the peak from about
+//! 306 MB to 281 MB and the time from about 470 ms to 430 ms. The tree
+//! alone takes about 188 MB, so it, rather than the tokens, is what
+//! dominates the memory.
+
+extern crate syntax;
+extern crate super_tiny_compiler;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use syntax::RstFile;
+use super_tiny_compiler::TINY;
+
+struct CountingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(current, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the peak number of bytes allocated by `f` on top of what was
+/// allocated before. The time `f` took is only printed.
+fn measure<F: FnOnce() -> RstFile>(name: &str, f: F) -> usize {
+    let base = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(base, Ordering::SeqCst);
+    let start = Instant::now();
+    let file = f();
+    let elapsed = start.elapsed();
+    let peak = PEAK.load(Ordering::SeqCst) - base;
+    println!("{:>6}: {:>8.1} ms, peak {:>6.1} MB, tree {:>6.1} MB",
+             name, elapsed.as_secs_f64() * 1000.0, peak as f64 / 1e6,
+             (CURRENT.load(Ordering::SeqCst) - base) as f64 / 1e6);
+    drop(file);
+    peak
+}
+
+fn main() {
+    let mut text = String::new();
+    let mut i = 0;
+    while text.len() < 4_000_000 {
+        text.push_str(&format!("(add {} (subtract \"foo\" bar) (baz))\n", i));
+        i += 1;
+    }
+    println!("\ninput: {:.1} MB", text.len() as f64 / 1e6);
+
+    let eager = measure("eager", || RstFile::new(TINY.tokenize(text.clone())));
+    let lazy = measure("lazy", || TINY.parse(text.clone()));
+    assert!(lazy < eager);
+}
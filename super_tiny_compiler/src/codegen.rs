@@ -51,4 +51,4 @@ add(2, subtract(4, 2));
 "#.trim_left();
 
     assert_eq!(expect, actual);
-}
\ No newline at end of file
+}
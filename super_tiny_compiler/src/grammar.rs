@@ -0,0 +1,24 @@
+grammar! {
+    tokens {
+        LPAREN = "lparen",
+        RPAREN = "rparen",
+        NUMBER = "number",
+        STRING = "string",
+        ID = "id",
+        COMMENT = "comment",
+    }
+
+    nodes {
+        TINY_FILE = "file",
+        LITERAL = "literal",
+        LIST = "list",
+    }
+
+    ast {
+        Program(TINY_FILE) { elements: [ListElement] }
+        List(LIST) { elements: [ListElement] }
+        Literal(LITERAL) {}
+        Variable(ID) {}
+        enum ListElement { List, Literal, Variable }
+    }
+}
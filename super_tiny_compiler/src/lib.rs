@@ -0,0 +1,21 @@
+#[macro_use]
+extern crate syntax;
+
+mod grammar;
+mod rst;
+mod ast;
+mod target;
+mod codegen;
+
+pub use rst::TINY;
+
+pub fn parse_tiny(text: String) -> syntax::RstFile {
+    rst::TINY.parse(text)
+}
+
+/// Translates a file without syntax errors into JavaScript.
+pub fn compile(rst: syntax::RstFile) -> String {
+    let ast = ast::AstFile::new(rst);
+    let program = target::translate(&ast);
+    codegen::generate(&program)
+}
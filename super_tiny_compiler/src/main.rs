@@ -1,25 +1,17 @@
-extern crate syntax;
+extern crate super_tiny_compiler;
 
 use std::io::Read;
 
-mod rst;
-mod ast;
-mod target;
-mod codegen;
-
-pub fn parse_tiny(text: String) -> syntax::RstFile {
-    let tokens = syntax::TokenFile::new(text, &rst::tiny_tokenizer);
-    syntax::RstFile::new(tokens, rst::TINY_FILE, &rst::tiny_parser)
-}
+use super_tiny_compiler::{parse_tiny, compile};
 
 fn main() {
     let mut input = String::new();
     std::io::stdin().read_to_string(&mut input).unwrap();
 
     let rst = parse_tiny(input);
-    let ast = ast::AstFile::new(rst);
-    let program = target::translate(&ast);
-    let code = codegen::generate(&program);
-    println!("{}", code);
+    if rst.diagnostics().has_errors() {
+        eprint!("{}", rst.diagnostics().render("<stdin>", rst.text()));
+        std::process::exit(1);
+    }
+    println!("{}", compile(rst));
 }
-
@@ -1,63 +1,48 @@
-use syntax::{self, RstBuilder, TokenBuilder, NodeType, WHITESPACE};
+use syntax::{Language, Lexer, RstBuilder, TokenBuilder, WHITESPACE};
 
-pub const LPAREN: NodeType = NodeType(03, "lparen");
-pub const RPAREN: NodeType = NodeType(04, "rparen");
-pub const NUMBER: NodeType = NodeType(05, "number");
-pub const STRING: NodeType = NodeType(06, "string");
-pub const ID: NodeType = NodeType(07, "id");
+use grammar::{LPAREN, RPAREN, NUMBER, STRING, ID, COMMENT, TINY_FILE, LITERAL, LIST, NODE_TYPES};
 
-pub const TINY_FILE: NodeType = NodeType(08, "file");
-pub const LITERAL: NodeType = NodeType(09, "literal");
-pub const LIST: NodeType = NodeType(10, "list");
+pub static TINY: Language = Language {
+    name: "tiny",
+    node_types: NODE_TYPES,
+    file_type: TINY_FILE,
+    trivia: &[WHITESPACE, COMMENT],
+    comment_owners: &[LIST],
+    tokenizer: tiny_tokenizer,
+    parser: tiny_parser,
+    reparsers: &[(LIST, reparse_list)],
+};
 
 pub fn tiny_tokenizer(builder: &mut TokenBuilder) {
-    let text_tokens = [(LPAREN, '('), (RPAREN, ')')];
-    let pred_tokens: &[(NodeType, &Fn(char) -> bool)] = &[
-        (ID, &char::is_alphabetic),
-        (WHITESPACE, &char::is_whitespace),
-        (NUMBER, &|c| c.is_digit(10)),
-    ];
-    loop {
-        if builder.try_text_token(&text_tokens) || builder.try_pred_token(&pred_tokens){
-            continue
-        }
-
-        let c = match builder.peek() {
-            Some(c) => c,
-            None => break
-        };
-
-        builder.bump();
-        match c {
-            '"' => loop {
-                match builder.peek() {
-                    Some('"') => {
-                        builder.bump();
-                        builder.emit(STRING);
-                        break;
-                    }
-                    Some(_) => builder.bump(),
-                    None => {
-                        builder.error();
-                        break;
-                    }
-                }
-            },
-
-            _ => builder.error()
-        }
+    thread_local! {
+        static LEXER: Lexer = Lexer::with_errors(&[
+            (LPAREN, r"\("),
+            (RPAREN, r"\)"),
+            (ID, r"\p{Alphabetic}+"),
+            (WHITESPACE, r"\p{White_Space}+"),
+            (COMMENT, r";[^\n]*"),
+            (NUMBER, r"\d+"),
+            (STRING, r#""[^"]*""#),
+        ], &[
+            // Spans to the end of file.
+            (r#""[^"]*"#, "unterminated string literal"),
+        ]);
     }
+    LEXER.with(|lexer| lexer.next_token(builder))
 }
 
 pub fn tiny_parser(builder: &mut RstBuilder) {
-    loop {
-        builder.skip_ws();
+    while builder.peek().is_some() {
         if !parse(builder) {
-            break
+            builder.err_and_bump("expected an expression");
         }
     }
 }
 
+fn reparse_list(builder: &mut RstBuilder) {
+    parse(builder);
+}
+
 fn parse(builder: &mut RstBuilder) -> bool {
     let ty = match builder.peek() {
         Some(ty) => ty,
@@ -65,11 +50,10 @@ fn parse(builder: &mut RstBuilder) -> bool {
     };
 
     match ty {
-        WHITESPACE => panic!("Leading ws not chomped!"),
         NUMBER | STRING => {
-            builder.start(LITERAL);
+            let m = builder.start(LITERAL);
             builder.bump();
-            builder.finish(LITERAL);
+            builder.complete(m);
             true
         }
         ID => {
@@ -77,18 +61,19 @@ fn parse(builder: &mut RstBuilder) -> bool {
             true
         },
         LPAREN => {
-            builder.start(LIST);
+            let m = builder.start(LIST);
             builder.bump();
             loop {
-                builder.skip_ws();
-                if !parse(builder) {
-                    break
+                match builder.peek() {
+                    None | Some(RPAREN) => break,
+                    Some(_) => if !parse(builder) {
+                        builder.err_and_bump("expected an expression");
+                    }
                 }
-                builder.skip_ws();
             }
 
             builder.eat(RPAREN);
-            builder.finish(LIST);
+            builder.complete(m);
             true
         }
         _ => false
@@ -97,15 +82,20 @@ fn parse(builder: &mut RstBuilder) -> bool {
 
 #[cfg(test)]
 fn check_tokenizer(text: &str, expected: &str) {
-    syntax::check_tokenizer(&tiny_tokenizer, text, expected);
+    ::syntax::check_tokenizer(&TINY, text, expected);
 }
 
 #[cfg(test)]
 fn check_parser(text: &str, expected: &str) {
-    syntax::check_parser(&tiny_tokenizer, &tiny_parser, TINY_FILE, text, expected);
+    ::syntax::check_parser(&TINY, text, expected);
 }
 
 
+#[test]
+fn test_node_types() {
+    TINY.check_node_types();
+}
+
 #[test]
 fn test_tokenizer() {
     check_tokenizer(r#"(foo "hello"  1)"#, r#"
@@ -116,6 +106,25 @@ string "\"hello\""
 whitespace "  "
 number "1"
 rparen ")"
+"#);
+
+    check_tokenizer(r#"(foo $ "bar)"#, r#"
+lparen "("
+id "foo"
+whitespace " "
+error "$"
+whitespace " "
+error "\"bar)"
+error [5; 6): unexpected character '$'
+error [7; 12): unterminated string literal
+"#);
+
+    check_tokenizer("(привет\u{a0}1)", r#"
+lparen "("
+id "привет"
+whitespace "\u{a0}"
+number "1"
+rparen ")"
 "#);
 }
 
@@ -155,4 +164,221 @@ list
     string "\"hello\""
   rparen ")"
     "#);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_empty_file() {
+    check_parser("", "");
+    let file = TINY.parse("  ".to_owned());
+    assert_eq!(file.dump(), "whitespace \"  \"\n");
+    assert_eq!(file.root().text_range(), ::syntax::Range::empty_at(0));
+}
+
+#[test]
+fn test_parser_recovery() {
+    check_parser(r#"(foo $ 1"#, r#"
+list
+  lparen "("
+  id "foo"
+  whitespace " "
+  error
+    error "$"
+  whitespace " "
+  literal
+    number "1"
+error [5; 6): unexpected character '$'
+error [5; 6): expected an expression
+error [8; 8): expected rparen
+    "#);
+
+    check_parser(r#") (bar"#, r#"
+error
+  rparen ")"
+whitespace " "
+list
+  lparen "("
+  id "bar"
+error [0; 1): expected an expression
+error [6; 6): expected rparen
+    "#);
+}
+
+#[test]
+fn test_comments() {
+    check_parser("; doc\n(foo ; one\n 1) ; two\n\n; three\n\n(bar)", r#"
+list
+  comment "; doc"
+  whitespace "\n"
+  lparen "("
+  id "foo"
+  whitespace " "
+  comment "; one"
+  whitespace "\n "
+  literal
+    number "1"
+  rparen ")"
+whitespace " "
+comment "; two"
+whitespace "\n\n"
+comment "; three"
+whitespace "\n\n"
+list
+  lparen "("
+  id "bar"
+  rparen ")"
+"#);
+
+    let file = TINY.parse("(foo ; one\n 1)".to_owned());
+    let list = file.root().children().next().unwrap();
+    let children: Vec<_> = list.children().map(|n| n.ty()).collect();
+    assert_eq!(children, vec![LPAREN, ID, LITERAL, RPAREN]);
+}
+
+#[test]
+fn test_comment_ranges() {
+    let file = TINY.parse("; doc\n(foo 1)".to_owned());
+    let list = file.root().children().next().unwrap();
+    assert_eq!(list.text(), "; doc\n(foo 1)");
+    assert_eq!(list.text_range(), ::syntax::Range::from_to(6, 13));
+    let d = ::syntax::Diagnostic::error(list.text_range(), "unknown function");
+    assert_eq!(d.render("<test>", file.text()), "\
+error: unknown function
+ --> <test>:2:1
+  |
+2 | (foo 1)
+  | ^^^^^^^
+");
+}
+
+#[test]
+fn test_compile_comments() {
+    let file = TINY.parse("; doc\n(add ; one\n 1 2)".to_owned());
+    assert_eq!(::compile(file), "add(1, 2);\n");
+}
+
+#[test]
+fn test_incremental_reparse() {
+    let text = r#"(foo 1 (bar "x" 2) ()) baz (1 (2 (3 "four")))"#;
+    let fragments = [" (foo 1)", " 92", " bar", " \"hi\"", " ()", " ; c\n", "\n", " ", "(", ")", "\"", "$"];
+    ::syntax::check_incremental(&TINY, text, &fragments, 2000, 1000);
+}
+
+#[test]
+fn test_edit_multibyte() {
+    use syntax::Range;
+
+    let file = TINY.parse("(привет \"мир\")".to_owned());
+    let file = file.edit(Range::from_to(5, 9), "ыв");
+    assert_eq!(file.text(), "(прывет \"мир\")");
+    let file = file.edit(Range::from_to(21, 21), "ы");
+    assert_eq!(file.text(), "(прывет \"миры\")");
+    assert_eq!(file.dump(), TINY.parse(file.text().to_owned()).dump());
+}
+
+#[test]
+#[should_panic(expected = "edit: range [2; 4) is not on char boundaries")]
+fn test_edit_inside_char() {
+    TINY.parse("(привет)".to_owned()).edit(::syntax::Range::from_to(2, 4), "x");
+}
+
+#[test]
+fn test_traversal() {
+    use syntax::WalkEvent;
+
+    let file = TINY.parse(r#"(foo (1 "x")) bar"#.to_owned());
+    let root = file.root();
+    let list = root.children().next().unwrap();
+    let inner = list.children().find(|n| n.ty() == LIST).unwrap();
+    let one = inner.children().nth(1).unwrap();
+
+    assert_eq!(one.text(), "1");
+    assert_eq!(one.parent(), Some(inner));
+    assert_eq!(inner.parent(), Some(list));
+    assert_eq!(root.parent(), None);
+    assert_eq!(one.ancestors().map(|n| n.ty()).collect::<Vec<_>>(),
+               vec![LITERAL, LIST, LIST, TINY_FILE]);
+
+    assert_eq!(inner.prev_sibling().unwrap().ty(), WHITESPACE);
+    assert_eq!(inner.next_sibling().unwrap().ty(), RPAREN);
+    assert_eq!(list.prev_sibling(), None);
+    assert_eq!(list.next_sibling().unwrap().next_sibling().unwrap().text(), "bar");
+
+    assert_eq!(list.first_token().unwrap().text(), "(");
+    assert_eq!(list.last_token().unwrap().text(), ")");
+    assert_eq!(root.last_token().unwrap().text(), "bar");
+
+    let tokens: String = root.descendants()
+        .filter(|n| n.children_with_trivia().next().is_none())
+        .map(|n| n.text())
+        .collect();
+    assert_eq!(tokens, file.text());
+
+    let mut depth = 0;
+    let mut max_depth = 0;
+    for event in root.walk() {
+        match event {
+            WalkEvent::Enter(_) => depth += 1,
+            WalkEvent::Leave(_) => depth -= 1,
+        }
+        max_depth = max_depth.max(depth);
+    }
+    assert_eq!((depth, max_depth), (0, 5));
+}
+
+#[test]
+fn test_token_at_offset() {
+    use syntax::{Range, TokenAtOffset};
+
+    let file = TINY.parse(r#"(foo 92)"#.to_owned());
+    let token = |offset| match file.token_at_offset(offset) {
+        TokenAtOffset::None => vec![],
+        TokenAtOffset::Single(t) => vec![t.text()],
+        TokenAtOffset::Between(l, r) => vec![l.text(), r.text()],
+    };
+    assert_eq!(token(0), vec!["("]);
+    assert_eq!(token(1), vec!["(", "foo"]);
+    assert_eq!(token(2), vec!["foo"]);
+    assert_eq!(token(4), vec!["foo", " "]);
+    assert_eq!(token(6), vec!["92"]);
+    assert_eq!(token(7), vec!["92", ")"]);
+    assert_eq!(token(8), vec![")"]);
+    assert_eq!(token(9), Vec::<&str>::new());
+
+    let covering = |lo, hi| {
+        let node = file.covering_node(Range::from_to(lo, hi));
+        (node.ty(), node.text())
+    };
+    assert_eq!(covering(2, 3), (ID, "foo"));
+    assert_eq!(covering(1, 4), (ID, "foo"));
+    assert_eq!(covering(5, 7), (NUMBER, "92"));
+    assert_eq!(covering(4, 7), (LIST, "(foo 92)"));
+    assert_eq!(covering(6, 6), (NUMBER, "92"));
+    assert_eq!(covering(4, 4), (ID, "foo"));
+    assert_eq!(covering(2, 6), (LIST, "(foo 92)"));
+    assert_eq!(covering(0, 8), (LIST, "(foo 92)"));
+}
+
+#[test]
+fn test_node_ranges() {
+    use syntax::Range;
+
+    let file = TINY.parse("  (foo 92 )".to_owned());
+    let list = file.root().children().next().unwrap();
+    assert!(!list.is_leaf());
+    assert!(list.token().is_none());
+    assert_eq!(list.range(), Range::from_to(2, 11));
+    assert_eq!(file.root().range(), Range::from_to(0, 11));
+    assert_eq!(file.root().text_range(), Range::from_to(2, 11));
+
+    let literal = list.children().nth(2).unwrap();
+    assert_eq!(literal.ty(), LITERAL);
+    let number = literal.children().next().unwrap();
+    assert!(number.is_leaf());
+    let token = number.token().unwrap();
+    assert_eq!((token.ty, token.text, token.range), (NUMBER, "92", Range::from_to(7, 9)));
+
+    let empty = TINY.parse(String::new());
+    assert_eq!(empty.root().range(), Range::empty_at(0));
+    assert_eq!(empty.root().text_range(), Range::empty_at(0));
+    assert_eq!(empty.root().text(), "");
+}
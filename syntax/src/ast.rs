@@ -0,0 +1,182 @@
+use {Node, NodeType};
+
+/// A typed wrapper around a node of a particular type.
+pub trait AstElement<'f>: Sized {
+    fn ty() -> NodeType;
+
+    fn from_node(node: Node<'f>) -> Self;
+
+    fn node(&self) -> Node<'f>;
+
+    fn cast(node: Node<'f>) -> Option<Self> {
+        if node.ty() == Self::ty() {
+            Some(Self::from_node(node))
+        } else {
+            None
+        }
+    }
+}
+
+/// Declares the node types of a language and, optionally, typed AST
+/// wrappers for them.
+///
+/// ```ignore
+/// grammar! {
+///     tokens {
+///         LPAREN = "lparen",
+///         RPAREN = "rparen",
+///         ID = "id",
+///     }
+///     nodes {
+///         FILE = "file",
+///         LIST = "list",
+///     }
+///     ast {
+///         Program(FILE) { elements: [Element] }
+///         List(LIST) { head: Element, elements: [Element] }
+///         Variable(ID) {}
+///         enum Element { List, Variable }
+///     }
+/// }
+/// ```
+///
+/// Ids are assigned sequentially, and all types together with their
+/// names are collected into the `NODE_TYPES` table for
+/// `Language::node_types`. An `ast` struct wraps a node of the given
+/// type; a field `name: Type` becomes a method returning the first
+/// child which is a `Type`, and `name: [Type]` returns all such
+/// children. An `enum` is a union of previously declared wrappers. All
+/// wrappers implement `Debug` in terms of their fields.
+#[macro_export]
+macro_rules! grammar {
+    (
+        tokens { $($token:ident = $token_name:expr,)* }
+        nodes { $($node:ident = $node_name:expr,)* }
+        $(ast { $($ast:tt)* })*
+    ) => {
+        #[allow(non_camel_case_types, dead_code)]
+        enum __NodeTypeIds { $($token,)* $($node,)* }
+
+        $(pub const $token: $crate::NodeType =
+            $crate::NodeType($crate::FIRST_LANGUAGE_ID + __NodeTypeIds::$token as u32);)*
+        $(pub const $node: $crate::NodeType =
+            $crate::NodeType($crate::FIRST_LANGUAGE_ID + __NodeTypeIds::$node as u32);)*
+
+        pub static NODE_TYPES: &[($crate::NodeType, &str)] = &[
+            $(($token, $token_name),)*
+            $(($node, $node_name),)*
+        ];
+
+        $(grammar!(@ast $($ast)*);)*
+    };
+
+    (@ast) => {};
+
+    (@ast enum $name:ident { $($variant:ident),* $(,)* } $($rest:tt)*) => {
+        #[derive(Clone, Copy)]
+        pub enum $name<'f> {
+            $($variant($variant<'f>),)*
+        }
+
+        impl<'f> $name<'f> {
+            pub fn cast(node: $crate::Node<'f>) -> Option<$name<'f>> {
+                #[allow(unused_imports)]
+                use $crate::AstElement;
+                $(if let Some(element) = $variant::cast(node) {
+                    return Some($name::$variant(element));
+                })*
+                None
+            }
+
+            pub fn node(&self) -> $crate::Node<'f> {
+                #[allow(unused_imports)]
+                use $crate::AstElement;
+                match *self {
+                    $($name::$variant(ref element) => element.node(),)*
+                }
+            }
+        }
+
+        impl<'f> ::std::fmt::Debug for $name<'f> {
+            fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                match *self {
+                    $($name::$variant(ref element) => element.fmt(fmt),)*
+                }
+            }
+        }
+
+        grammar!(@ast $($rest)*);
+    };
+
+    (@ast $name:ident($ty:ident) {} $($rest:tt)*) => {
+        grammar!(@struct $name $ty);
+
+        impl<'f> ::std::fmt::Debug for $name<'f> {
+            fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                fmt.debug_tuple(stringify!($name))
+                    .field(&self.node.text())
+                    .finish()
+            }
+        }
+
+        grammar!(@ast $($rest)*);
+    };
+
+    (@ast $name:ident($ty:ident) { $($field:ident : $kind:tt),* $(,)* } $($rest:tt)*) => {
+        grammar!(@struct $name $ty);
+
+        $(grammar!(@field $name $field $kind);)*
+
+        impl<'f> ::std::fmt::Debug for $name<'f> {
+            fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                fmt.debug_struct(stringify!($name))
+                    $(.field(stringify!($field), &self.$field()))*
+                    .finish()
+            }
+        }
+
+        grammar!(@ast $($rest)*);
+    };
+
+    (@struct $name:ident $ty:ident) => {
+        #[derive(Clone, Copy)]
+        pub struct $name<'f> {
+            node: $crate::Node<'f>,
+        }
+
+        impl<'f> $crate::AstElement<'f> for $name<'f> {
+            fn ty() -> $crate::NodeType {
+                $ty
+            }
+
+            fn from_node(node: $crate::Node<'f>) -> Self {
+                assert_eq!(node.ty(), $ty);
+                $name { node: node }
+            }
+
+            fn node(&self) -> $crate::Node<'f> {
+                self.node
+            }
+        }
+    };
+
+    (@field $name:ident $field:ident [$ty:ident]) => {
+        impl<'f> $name<'f> {
+            pub fn $field(&self) -> Vec<$ty<'f>> {
+                #[allow(unused_imports)]
+                use $crate::AstElement;
+                self.node.children().filter_map($ty::cast).collect()
+            }
+        }
+    };
+
+    (@field $name:ident $field:ident $ty:ident) => {
+        impl<'f> $name<'f> {
+            pub fn $field(&self) -> Option<$ty<'f>> {
+                #[allow(unused_imports)]
+                use $crate::AstElement;
+                self.node.children().filter_map($ty::cast).next()
+            }
+        }
+    };
+}
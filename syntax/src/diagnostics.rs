@@ -0,0 +1,250 @@
+use std::fmt::{self, Write};
+
+use {Range, LineIndex};
+
+macro_rules! w {
+    ($($tt:tt)*) => { write!($($tt)*).expect("write to string can't fail") }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        fmt.write_str(name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub range: Range,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub range: Range,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new<M: Into<String>>(severity: Severity, range: Range, message: M) -> Diagnostic {
+        Diagnostic {
+            severity: severity,
+            message: message.into(),
+            range: range,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error<M: Into<String>>(range: Range, message: M) -> Diagnostic {
+        Diagnostic::new(Severity::Error, range, message)
+    }
+
+    pub fn warning<M: Into<String>>(range: Range, message: M) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, range, message)
+    }
+
+    pub fn with_label<M: Into<String>>(mut self, range: Range, message: M) -> Diagnostic {
+        self.labels.push(Label { range: range, message: message.into() });
+        self
+    }
+
+    pub fn with_note<M: Into<String>>(mut self, note: M) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic as a rustc-style snippet of `text`.
+    pub fn render(&self, file_name: &str, text: &str) -> String {
+        self.render_with(file_name, text, &LineIndex::new(text))
+    }
+
+    /// Renders just the message and the notes, for problems which don't
+    /// point to the source, like a failure of an external tool.
+    pub fn render_message(&self) -> String {
+        let mut buff = String::new();
+        w!(buff, "{}: {}\n", self.severity, self.message);
+        for note in self.notes.iter() {
+            w!(buff, "  = note: {}\n", note);
+        }
+        buff
+    }
+
+    fn render_with(&self, file_name: &str, text: &str, index: &LineIndex) -> String {
+        let mut buff = String::new();
+        let (line, col) = char_line_col(text, index, self.range.lo());
+        w!(buff, "{}: {}\n", self.severity, self.message);
+
+        let mut annotations = vec![(self.range, '^', "")];
+        annotations.extend(self.labels.iter().map(|l| (l.range, '-', &*l.message)));
+        let mut lines: Vec<u32> = annotations.iter()
+            .map(|&(range, _, _)| index.line_col(range.lo()).line)
+            .collect();
+        lines.sort();
+        lines.dedup();
+
+        let gutter = " ".repeat((lines.last().unwrap() + 1).to_string().len());
+        w!(buff, "{}--> {}:{}:{}\n", gutter, file_name, line + 1, col + 1);
+        w!(buff, "{} |\n", gutter);
+        let mut prev_line = None;
+        for &line in lines.iter() {
+            if let Some(prev) = prev_line {
+                if prev + 1 < line {
+                    w!(buff, "{}...\n", gutter);
+                }
+            }
+            prev_line = Some(line);
+
+            let line_text = line_text(text, index, line);
+            w!(buff, "{:>width$} | {}\n", line + 1, line_text, width = gutter.len());
+            for &(range, marker, message) in annotations.iter() {
+                let (l, col) = char_line_col(text, index, range.lo());
+                if l != line {
+                    continue;
+                }
+                let line_end = index.line_start(line) + line_text.len() as u32;
+                let hi = range.hi().min(line_end).max(range.lo());
+                let len = text[Range::from_to(range.lo(), hi)].chars().count().max(1);
+                let mut underline = format!("{} | {}{}", gutter, " ".repeat(col as usize),
+                                            marker.to_string().repeat(len));
+                if !message.is_empty() {
+                    w!(underline, " {}", message);
+                }
+                w!(buff, "{}\n", underline);
+            }
+        }
+
+        if !self.notes.is_empty() {
+            w!(buff, "{} |\n", gutter);
+        }
+        for note in self.notes.iter() {
+            w!(buff, "{} = note: {}\n", gutter, note);
+        }
+        buff
+    }
+}
+
+/// A sink for diagnostics produced by the tokenizer, the parser and
+/// any later passes.
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics { diagnostics: Vec::new() }
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic)
+    }
+
+    pub fn error<M: Into<String>>(&mut self, range: Range, message: M) {
+        self.push(Diagnostic::error(range, message))
+    }
+
+    pub fn extend(&mut self, other: Diagnostics) {
+        self.diagnostics.extend(other.diagnostics)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.diagnostics.truncate(len)
+    }
+
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    /// One line per diagnostic, for tests.
+    pub(crate) fn dump(&self) -> String {
+        let mut buff = String::new();
+        for d in self.iter() {
+            w!(buff, "{} {:?}: {}\n", d.severity, d.range, d.message);
+        }
+        buff
+    }
+
+    pub fn render(&self, file_name: &str, text: &str) -> String {
+        let index = LineIndex::new(text);
+        self.iter()
+            .map(|d| d.render_with(file_name, text, &index))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Line and column in characters, which is what a terminal displays.
+fn char_line_col(text: &str, index: &LineIndex, offset: u32) -> (u32, u32) {
+    let line = index.line_col(offset).line;
+    let line_start = index.line_start(line);
+    let col = text[Range::from_to(line_start, offset)].chars().count() as u32;
+    (line, col)
+}
+
+fn line_text<'t>(text: &'t str, index: &LineIndex, line: u32) -> &'t str {
+    let lo = index.line_start(line);
+    let hi = if line + 1 < index.line_count() {
+        index.line_start(line + 1)
+    } else {
+        text.len() as u32
+    };
+    text[Range::from_to(lo, hi)].trim_end_matches('\n').trim_end_matches('\r')
+}
+
+#[test]
+fn test_render() {
+    let text = "foo = 1\nbar = foo +\n";
+    let d = Diagnostic::error(Range::from_to(18, 19), "expected an expression")
+        .with_label(Range::from_to(0, 3), "`foo` defined here")
+        .with_note("binary operators need two operands");
+    let expected = "\
+error: expected an expression
+ --> main.pyt:2:11
+  |
+1 | foo = 1
+  | --- `foo` defined here
+2 | bar = foo +
+  |           ^
+  |
+  = note: binary operators need two operands
+";
+    assert_eq!(d.render("main.pyt", text), expected);
+}
+
+#[test]
+fn test_render_message() {
+    let d = Diagnostic::error(Range::empty_at(0), "linking failed")
+        .with_note("undefined symbol: main");
+    assert_eq!(d.render_message(), "error: linking failed\n  = note: undefined symbol: main\n");
+}
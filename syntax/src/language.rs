@@ -0,0 +1,141 @@
+use std::fmt;
+
+use {NodeType, TokenFile, TokenBuilder, RstFile, RstBuilder, ERROR, WHITESPACE};
+
+pub type Reparser = fn(&mut RstBuilder);
+
+/// Ids below this one are reserved for the node types shared by all
+/// languages, like `ERROR` and `WHITESPACE`.
+pub const FIRST_LANGUAGE_ID: u32 = 16;
+
+const BUILTIN_NODE_TYPES: &[(NodeType, &str)] = &[
+    (ERROR, "error"),
+    (WHITESPACE, "whitespace"),
+];
+
+/// Everything needed to turn text into an `RstFile`, and to update
+/// such a file incrementally after an edit.
+pub struct Language {
+    pub name: &'static str,
+    /// Names of the language specific node types, usually the
+    /// `NODE_TYPES` table generated by `grammar!`.
+    pub node_types: &'static [(NodeType, &'static str)],
+    pub file_type: NodeType,
+    /// Tokens which the parser skips, like `WHITESPACE` and comments.
+    pub trivia: &'static [NodeType],
+    /// Nodes which own the comments right before them, like statements
+    /// or lists, so that doc comments end up inside. Comments before
+    /// other nodes, like literals, stay in the parent.
+    pub comment_owners: &'static [NodeType],
+    /// Must be restartable at any token boundary: lexing a suffix of
+    /// the text which starts at a token should produce the same tokens.
+    /// It is called repeatedly while there's input left, and may return
+    /// after each token, so that tokens are produced only when the parser
+    /// needs them.
+    pub tokenizer: fn(&mut TokenBuilder),
+    pub parser: fn(&mut RstBuilder),
+    /// Nodes which can be reparsed in isolation, together with the parsing
+    /// functions that produce them. Such function is called with the
+    /// builder positioned at the first token of the node and must parse
+    /// exactly one node, exactly as the `parser` would at that position.
+    pub reparsers: &'static [(NodeType, Reparser)],
+}
+
+impl Language {
+    pub fn tokenize(&'static self, text: String) -> TokenFile {
+        TokenFile::new(text, self)
+    }
+
+    pub fn parse(&'static self, text: String) -> RstFile {
+        RstFile::parse(self, text)
+    }
+
+    pub fn is_trivia(&self, ty: NodeType) -> bool {
+        self.trivia.contains(&ty)
+    }
+
+    pub fn reparser(&self, ty: NodeType) -> Option<Reparser> {
+        self.reparsers.iter()
+            .find(|&&(t, _)| t == ty)
+            .map(|&(_, parser)| parser)
+    }
+
+    pub fn node_name(&self, ty: NodeType) -> &'static str {
+        BUILTIN_NODE_TYPES.iter().chain(self.node_types.iter())
+            .find(|&&(t, _)| t == ty)
+            .map(|&(_, name)| name)
+            .unwrap_or_else(|| panic!("{:?} is not a node type of {}", ty, self.name))
+    }
+
+    /// Panics if node types of the language clash with each other or with
+    /// the builtin ones, or if the language uses unregistered types.
+    pub fn check_node_types(&self) {
+        for (i, &(ty, name)) in self.node_types.iter().enumerate() {
+            assert!(ty.0 >= FIRST_LANGUAGE_ID,
+                    "{}: {} uses reserved id {}", self.name, name, ty.0);
+            for &(other_ty, other_name) in self.node_types[..i].iter() {
+                assert!(ty != other_ty,
+                        "{}: {} and {} have the same id {}", self.name, other_name, name, ty.0);
+                assert!(name != other_name,
+                        "{}: duplicate node type name {}", self.name, name);
+            }
+        }
+        self.node_name(self.file_type);
+        for &ty in self.trivia.iter().chain(self.comment_owners.iter()) {
+            self.node_name(ty);
+        }
+        for &(ty, _) in self.reparsers.iter() {
+            self.node_name(ty);
+        }
+    }
+}
+
+impl fmt::Debug for Language {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Language({})", self.name)
+    }
+}
+
+#[cfg(test)]
+fn tokenize_nothing(_: &mut TokenBuilder) {}
+
+#[cfg(test)]
+fn parse_nothing(_: &mut RstBuilder) {}
+
+#[test]
+fn test_node_name() {
+    static LANG: Language = Language {
+        name: "test",
+        node_types: &[(NodeType(FIRST_LANGUAGE_ID), "file")],
+        file_type: NodeType(FIRST_LANGUAGE_ID),
+        trivia: &[WHITESPACE],
+        comment_owners: &[],
+        tokenizer: tokenize_nothing,
+        parser: parse_nothing,
+        reparsers: &[],
+    };
+    LANG.check_node_types();
+    assert_eq!(LANG.node_name(NodeType(FIRST_LANGUAGE_ID)), "file");
+    assert_eq!(LANG.node_name(WHITESPACE), "whitespace");
+    assert_eq!(LANG.parse(String::new()).dump(), "");
+}
+
+#[test]
+#[should_panic(expected = "test: foo and bar have the same id 17")]
+fn test_duplicate_node_types() {
+    static LANG: Language = Language {
+        name: "test",
+        node_types: &[
+            (NodeType(FIRST_LANGUAGE_ID), "file"),
+            (NodeType(FIRST_LANGUAGE_ID + 1), "foo"),
+            (NodeType(FIRST_LANGUAGE_ID + 1), "bar"),
+        ],
+        file_type: NodeType(FIRST_LANGUAGE_ID),
+        trivia: &[WHITESPACE],
+        comment_owners: &[],
+        tokenizer: tokenize_nothing,
+        parser: parse_nothing,
+        reparsers: &[],
+    };
+    LANG.check_node_types();
+}
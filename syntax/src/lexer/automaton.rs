@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use super::regex::Regex;
+
+/// A Thompson NFA with a separate accepting state for each rule.
+struct Nfa {
+    states: Vec<NfaState>,
+}
+
+#[derive(Default)]
+struct NfaState {
+    epsilon: Vec<usize>,
+    edges: Vec<(u32, u32, usize)>,
+    accept: Option<usize>,
+}
+
+impl Nfa {
+    fn new_state(&mut self) -> usize {
+        self.states.push(NfaState::default());
+        self.states.len() - 1
+    }
+
+    fn epsilon(&mut self, from: usize, to: usize) {
+        self.states[from].epsilon.push(to);
+    }
+
+    /// Adds states matching `regex` between `from` and a new state, which
+    /// is returned.
+    fn add(&mut self, regex: &Regex, from: usize) -> usize {
+        match *regex {
+            Regex::Empty => from,
+            Regex::Class(ref ranges) => {
+                let to = self.new_state();
+                for &(lo, hi) in ranges.iter() {
+                    self.states[from].edges.push((lo, hi, to));
+                }
+                to
+            }
+            Regex::Concat(ref items) => items.iter().fold(from, |state, item| self.add(item, state)),
+            Regex::Alt(ref alternatives) => {
+                let to = self.new_state();
+                for alternative in alternatives.iter() {
+                    let start = self.new_state();
+                    self.epsilon(from, start);
+                    let end = self.add(alternative, start);
+                    self.epsilon(end, to);
+                }
+                to
+            }
+            Regex::Star(ref inner) => {
+                let start = self.new_state();
+                self.epsilon(from, start);
+                let end = self.add(inner, start);
+                self.epsilon(end, start);
+                start
+            }
+            Regex::Plus(ref inner) => {
+                let start = self.new_state();
+                self.epsilon(from, start);
+                let end = self.add(inner, start);
+                self.epsilon(end, start);
+                end
+            }
+            Regex::Opt(ref inner) => {
+                let end = self.add(inner, from);
+                self.epsilon(from, end);
+                end
+            }
+        }
+    }
+
+    fn closure(&self, states: &mut Vec<usize>) {
+        let mut stack = states.clone();
+        while let Some(state) = stack.pop() {
+            for &next in self.states[state].epsilon.iter() {
+                if !states.contains(&next) {
+                    states.push(next);
+                    stack.push(next);
+                }
+            }
+        }
+        states.sort();
+    }
+}
+
+pub struct Dfa {
+    states: Vec<DfaState>,
+}
+
+struct DfaState {
+    /// Sorted, non overlapping inclusive ranges of code points.
+    transitions: Vec<(u32, u32, usize)>,
+    /// The index of the first rule accepted in this state.
+    accept: Option<usize>,
+}
+
+impl Dfa {
+    /// Builds an automaton which recognizes the union of `rules`.
+    pub fn new(rules: &[Regex]) -> Dfa {
+        let mut nfa = Nfa { states: Vec::new() };
+        let start = nfa.new_state();
+        for (idx, rule) in rules.iter().enumerate() {
+            let rule_start = nfa.new_state();
+            nfa.epsilon(start, rule_start);
+            let end = nfa.add(rule, rule_start);
+            let accept = nfa.new_state();
+            nfa.epsilon(end, accept);
+            nfa.states[accept].accept = Some(idx);
+        }
+
+        let mut start = vec![start];
+        nfa.closure(&mut start);
+        let mut sets = vec![start.clone()];
+        let mut ids = HashMap::new();
+        ids.insert(start, 0);
+        let mut states = Vec::new();
+        while states.len() < sets.len() {
+            let set = sets[states.len()].clone();
+            let accept = set.iter().filter_map(|&s| nfa.states[s].accept).min();
+
+            let mut edges: Vec<(u32, u32, usize)> = set.iter()
+                .flat_map(|&s| nfa.states[s].edges.iter().cloned())
+                .collect();
+            edges.sort();
+            let mut bounds: Vec<u32> = edges.iter()
+                .flat_map(|&(lo, hi, _)| vec![lo, hi + 1])
+                .collect();
+            bounds.sort();
+            bounds.dedup();
+
+            // Sweep over the windows, keeping the edges which cover the
+            // current one: classes like `\p{Alphabetic}` have hundreds of
+            // ranges.
+            let mut transitions: Vec<(u32, u32, usize)> = Vec::new();
+            let mut next_edge = 0;
+            let mut active: Vec<(u32, usize)> = Vec::new();
+            for window in bounds.windows(2) {
+                let (lo, hi) = (window[0], window[1] - 1);
+                active.retain(|&(h, _)| lo <= h);
+                while next_edge < edges.len() && edges[next_edge].0 <= lo {
+                    let (_, h, to) = edges[next_edge];
+                    active.push((h, to));
+                    next_edge += 1;
+                }
+                let mut targets: Vec<usize> = active.iter()
+                    .map(|&(_, to)| to)
+                    .collect();
+                if targets.is_empty() {
+                    continue;
+                }
+                targets.sort();
+                targets.dedup();
+                nfa.closure(&mut targets);
+                let target = match ids.get(&targets) {
+                    Some(&id) => id,
+                    None => {
+                        let id = sets.len();
+                        sets.push(targets.clone());
+                        ids.insert(targets, id);
+                        id
+                    }
+                };
+                match transitions.last_mut() {
+                    Some(last) if last.1 + 1 == lo && last.2 == target => {
+                        last.1 = hi;
+                        continue;
+                    }
+                    _ => {}
+                }
+                transitions.push((lo, hi, target));
+            }
+            states.push(DfaState { transitions: transitions, accept: accept });
+        }
+        Dfa { states: states }
+    }
+
+    /// Returns the rule with the longest non-empty match at the start of
+    /// `chars`, together with the length of the match in bytes.
+    pub fn longest_match<I: Iterator<Item=char>>(&self, chars: I) -> Option<(usize, usize)> {
+        let mut state = 0;
+        let mut len = 0;
+        let mut result = None;
+        for c in chars {
+            state = match self.next(state, c as u32) {
+                Some(state) => state,
+                None => break,
+            };
+            len += c.len_utf8();
+            if let Some(rule) = self.states[state].accept {
+                result = Some((rule, len));
+            }
+        }
+        result
+    }
+
+    fn next(&self, state: usize, c: u32) -> Option<usize> {
+        let transitions = &self.states[state].transitions;
+        let idx = match transitions.binary_search_by_key(&c, |&(lo, _, _)| lo) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let (_, hi, to) = transitions[idx];
+        if c <= hi { Some(to) } else { None }
+    }
+}
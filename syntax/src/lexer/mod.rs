@@ -0,0 +1,145 @@
+mod regex;
+mod automaton;
+
+use {NodeType, TokenBuilder};
+
+use self::automaton::Dfa;
+
+/// A tokenizer generated from a list of `(token type, regex)` rules.
+///
+/// At each position the rule with the longest match wins. If several
+/// rules match the same number of characters, the one which comes first
+/// wins, so keywords should be listed before identifiers. Matches of an
+/// `ERROR` rule are reported as invalid tokens.
+pub struct Lexer {
+    /// The type of token or the error message for each rule.
+    rules: Vec<Result<NodeType, String>>,
+    dfa: Dfa,
+}
+
+impl Lexer {
+    /// Panics if some regex is invalid.
+    pub fn new(rules: &[(NodeType, &str)]) -> Lexer {
+        Lexer::with_errors(rules, &[])
+    }
+
+    /// Like `new`, but also takes `(regex, message)` rules for malformed
+    /// tokens, like unterminated string literals. Their matches become
+    /// `ERROR` tokens with the corresponding message. These rules have
+    /// lower priority than the token ones.
+    pub fn with_errors(rules: &[(NodeType, &str)], errors: &[(&str, &str)]) -> Lexer {
+        let regexes: Vec<_> = rules.iter().map(|&(_, regex)| regex)
+            .chain(errors.iter().map(|&(regex, _)| regex))
+            .map(|regex| match self::regex::parse(regex) {
+                Ok(regex) => regex,
+                Err(message) => panic!("invalid regex {:?}: {}", regex, message),
+            })
+            .collect();
+        Lexer {
+            rules: rules.iter().map(|&(ty, _)| Ok(ty))
+                .chain(errors.iter().map(|&(_, message)| Err(message.to_owned())))
+                .collect(),
+            dfa: Dfa::new(&regexes),
+        }
+    }
+
+    /// Splits the whole input into tokens.
+    pub fn tokenize(&self, builder: &mut TokenBuilder) {
+        while builder.peek().is_some() {
+            self.next_token(builder);
+        }
+    }
+
+    /// Emits a single token. A character which doesn't start any token is
+    /// reported as unexpected.
+    pub fn next_token(&self, builder: &mut TokenBuilder) {
+        if let Some(c) = builder.peek() {
+            if !builder.try_lex(self) {
+                builder.bump();
+                builder.error_with(format!("unexpected character {:?}", c));
+            }
+        }
+    }
+
+    /// Returns the type and the length in bytes of the longest token at
+    /// the start of `chars`. Matches of error rules have `ERROR` type.
+    pub fn longest_match<I: Iterator<Item=char>>(&self, chars: I) -> Option<(NodeType, usize)> {
+        self.longest_match_rule(chars)
+            .map(|(rule, len)| (rule.unwrap_or(::ERROR), len))
+    }
+
+    pub(crate) fn longest_match_rule<I: Iterator<Item=char>>(&self, chars: I)
+        -> Option<(Result<NodeType, &str>, usize)>
+    {
+        self.dfa.longest_match(chars)
+            .map(|(rule, len)| {
+                let rule = match self.rules[rule] {
+                    Ok(ty) => Ok(ty),
+                    Err(ref message) => Err(&message[..]),
+                };
+                (rule, len)
+            })
+    }
+}
+
+#[cfg(test)]
+fn check_lexer(rules: &[(NodeType, &str)], text: &str, expected: &[(NodeType, &str)]) {
+    let lexer = Lexer::new(rules);
+    let mut actual = Vec::new();
+    let mut rest = text;
+    while let Some((ty, len)) = lexer.longest_match(rest.chars()) {
+        actual.push((ty, &rest[..len]));
+        rest = &rest[len..];
+    }
+    assert!(rest.is_empty(), "failed to lex {:?}", rest);
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn test_longest_match() {
+    let (eq, eq_eq, pow, mul, ident, kw_if, number, ws) =
+        (NodeType(20), NodeType(21), NodeType(22), NodeType(23),
+         NodeType(24), NodeType(25), NodeType(26), NodeType(27));
+    let rules = [
+        (eq, "="), (eq_eq, "=="), (pow, "\\*\\*"), (mul, "\\*"),
+        (kw_if, "if"), (ident, "[a-zA-Z_]\\w*"),
+        (number, "\\d+(\\.\\d+)?"), (ws, "\\s+"),
+    ];
+    check_lexer(&rules, "if iffy == foo_1 = 2**3.14 *", &[
+        (kw_if, "if"), (ws, " "), (ident, "iffy"), (ws, " "), (eq_eq, "=="), (ws, " "),
+        (ident, "foo_1"), (ws, " "), (eq, "="), (ws, " "),
+        (number, "2"), (pow, "**"), (number, "3.14"), (ws, " "), (mul, "*"),
+    ]);
+}
+
+#[test]
+fn test_no_match() {
+    let lexer = Lexer::new(&[(NodeType(20), "ab|abcd")]);
+    assert_eq!(lexer.longest_match("abc".chars()), Some((NodeType(20), 2)));
+    assert_eq!(lexer.longest_match("abcd".chars()), Some((NodeType(20), 4)));
+    assert_eq!(lexer.longest_match("ba".chars()), None);
+    assert_eq!(lexer.longest_match("".chars()), None);
+}
+
+#[test]
+fn test_unicode() {
+    let (string, other) = (NodeType(20), NodeType(21));
+    check_lexer(&[(string, "'[^']*'"), (other, ".")], "'ы𝔸'ы", &[
+        (string, "'ы𝔸'"), (other, "ы"),
+    ]);
+}
+
+#[test]
+fn test_error_rules() {
+    let (string, ws) = (NodeType(20), NodeType(21));
+    let lexer = Lexer::with_errors(&[(string, "'[^']*'"), (ws, " +")], &[("'[^']*", "unterminated")]);
+    assert_eq!(lexer.longest_match("'a' 'b".chars()), Some((string, 3)));
+    assert_eq!(lexer.longest_match("'b".chars()), Some((::ERROR, 2)));
+    assert_eq!(lexer.longest_match_rule("'b".chars()), Some((Err("unterminated"), 2)));
+}
+
+#[test]
+#[should_panic(expected = "invalid regex \"(a\": unclosed group")]
+fn test_invalid_regex() {
+    Lexer::new(&[(NodeType(20), "(a")]);
+}
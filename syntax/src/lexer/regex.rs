@@ -0,0 +1,255 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Inclusive ranges of code points, sorted and non overlapping.
+pub type CharClass = Vec<(u32, u32)>;
+
+const MAX_CHAR: u32 = 0x10FFFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Regex {
+    Empty,
+    Class(CharClass),
+    Concat(Vec<Regex>),
+    Alt(Vec<Regex>),
+    Star(Box<Regex>),
+    Plus(Box<Regex>),
+    Opt(Box<Regex>),
+}
+
+/// Parses a regular expression. Supported are literal characters,
+/// `.` (anything but a newline), classes like `[a-z_]` and `[^"]`,
+/// escapes `\d`, `\w`, `\s`, `\n`, `\t`, `\r` and `\` followed by a
+/// punctuation character, Unicode classes `\p{Alphabetic}` and
+/// `\p{White_Space}`, groups `(..)`, alternatives `|` and the `*`, `+`
+/// and `?` repetitions. `\d`, `\w` and `\s` match ASCII only.
+pub fn parse(regex: &str) -> Result<Regex, String> {
+    let mut parser = RegexParser { chars: regex.chars().peekable() };
+    let result = parser.alt()?;
+    match parser.chars.next() {
+        None => Ok(result),
+        Some(c) => Err(format!("unexpected {:?}", c)),
+    }
+}
+
+struct RegexParser<'r> {
+    chars: Peekable<Chars<'r>>,
+}
+
+impl<'r> RegexParser<'r> {
+    fn alt(&mut self) -> Result<Regex, String> {
+        let mut alternatives = vec![self.concat()?];
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            alternatives.push(self.concat()?);
+        }
+        Ok(if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Regex::Alt(alternatives) })
+    }
+
+    fn concat(&mut self) -> Result<Regex, String> {
+        let mut items = Vec::new();
+        loop {
+            match self.chars.peek() {
+                None | Some(&'|') | Some(&')') => break,
+                _ => items.push(self.repeat()?),
+            }
+        }
+        Ok(match items.len() {
+            0 => Regex::Empty,
+            1 => items.pop().unwrap(),
+            _ => Regex::Concat(items),
+        })
+    }
+
+    fn repeat(&mut self) -> Result<Regex, String> {
+        let mut result = self.atom()?;
+        loop {
+            result = match self.chars.peek() {
+                Some(&'*') => Regex::Star(Box::new(result)),
+                Some(&'+') => Regex::Plus(Box::new(result)),
+                Some(&'?') => Regex::Opt(Box::new(result)),
+                _ => return Ok(result),
+            };
+            self.chars.next();
+        }
+    }
+
+    fn atom(&mut self) -> Result<Regex, String> {
+        let c = self.chars.next().ok_or("unexpected end of regex")?;
+        let result = match c {
+            '(' => {
+                let result = self.alt()?;
+                if self.chars.next() != Some(')') {
+                    return Err("unclosed group".to_owned());
+                }
+                result
+            }
+            '[' => Regex::Class(self.class()?),
+            '.' => Regex::Class(negate(vec![('\n' as u32, '\n' as u32)])),
+            '\\' => Regex::Class(self.escape()?),
+            '*' | '+' | '?' => return Err(format!("nothing to repeat with {:?}", c)),
+            c => Regex::Class(vec![(c as u32, c as u32)]),
+        };
+        Ok(result)
+    }
+
+    fn class(&mut self) -> Result<CharClass, String> {
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let lo = match self.chars.next() {
+                None => return Err("unclosed character class".to_owned()),
+                Some(']') => break,
+                Some('\\') => {
+                    let escaped = self.escape()?;
+                    if escaped.len() != 1 || escaped[0].0 != escaped[0].1 {
+                        ranges.extend(escaped);
+                        continue;
+                    }
+                    escaped[0].0
+                }
+                Some(c) => c as u32,
+            };
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') && lookahead.peek().is_some_and(|&c| c != ']') {
+                self.chars.next();
+                let hi = match self.chars.next() {
+                    Some('\\') => {
+                        let escaped = self.escape()?;
+                        if escaped.len() != 1 || escaped[0].0 != escaped[0].1 {
+                            return Err("invalid range".to_owned());
+                        }
+                        escaped[0].0
+                    }
+                    Some(c) => c as u32,
+                    None => return Err("unclosed character class".to_owned()),
+                };
+                if hi < lo {
+                    return Err("invalid range".to_owned());
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        let ranges = normalize(ranges);
+        Ok(if negated { negate(ranges) } else { ranges })
+    }
+
+    fn escape(&mut self) -> Result<CharClass, String> {
+        let c = self.chars.next().ok_or("unexpected end of regex")?;
+        let single = |c: char| vec![(c as u32, c as u32)];
+        let result = match c {
+            'd' => vec![('0' as u32, '9' as u32)],
+            'w' => normalize(vec![
+                ('0' as u32, '9' as u32),
+                ('A' as u32, 'Z' as u32),
+                ('_' as u32, '_' as u32),
+                ('a' as u32, 'z' as u32),
+            ]),
+            's' => normalize(" \t\n\r".chars().map(|c| (c as u32, c as u32)).collect()),
+            'n' => single('\n'),
+            't' => single('\t'),
+            'r' => single('\r'),
+            'p' => self.unicode_class()?,
+            c if c.is_ascii_punctuation() => single(c),
+            c => return Err(format!("unknown escape \\{}", c)),
+        };
+        Ok(result)
+    }
+
+    /// Parses `{Name}` after `\p`.
+    fn unicode_class(&mut self) -> Result<CharClass, String> {
+        if self.chars.next() != Some('{') {
+            return Err("expected `{` after \\p".to_owned());
+        }
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some(c) => name.push(c),
+                None => return Err("unclosed Unicode class".to_owned()),
+            }
+        }
+        match &name[..] {
+            "Alphabetic" => Ok(class_of(char::is_alphabetic)),
+            "White_Space" => Ok(class_of(char::is_whitespace)),
+            _ => Err(format!("unknown Unicode class {}", name)),
+        }
+    }
+}
+
+/// All chars for which `pred` holds.
+fn class_of(pred: fn(char) -> bool) -> CharClass {
+    let mut result: CharClass = Vec::new();
+    for c in (0..MAX_CHAR + 1).filter_map(::std::char::from_u32).filter(|&c| pred(c)) {
+        let c = c as u32;
+        match result.last_mut() {
+            Some(last) if last.1 + 1 == c => last.1 = c,
+            _ => result.push((c, c)),
+        }
+    }
+    result
+}
+
+fn normalize(mut ranges: CharClass) -> CharClass {
+    ranges.sort();
+    let mut result: CharClass = Vec::with_capacity(ranges.len());
+    for (lo, hi) in ranges {
+        if let Some(last) = result.last_mut() {
+            if lo <= last.1 + 1 {
+                last.1 = last.1.max(hi);
+                continue;
+            }
+        }
+        result.push((lo, hi));
+    }
+    result
+}
+
+fn negate(ranges: CharClass) -> CharClass {
+    let mut result = Vec::new();
+    let mut next = 0;
+    for (lo, hi) in ranges {
+        if next < lo {
+            result.push((next, lo - 1));
+        }
+        next = hi + 1;
+    }
+    if next <= MAX_CHAR {
+        result.push((next, MAX_CHAR));
+    }
+    result
+}
+
+#[test]
+fn test_parse_regex() {
+    let class = |lo: char, hi: char| Regex::Class(vec![(lo as u32, hi as u32)]);
+    assert_eq!(parse("a|bc*").unwrap(), Regex::Alt(vec![
+        class('a', 'a'),
+        Regex::Concat(vec![class('b', 'b'), Regex::Star(Box::new(class('c', 'c')))]),
+    ]));
+    assert_eq!(parse("[a-c_]").unwrap(), Regex::Class(vec![
+        ('_' as u32, '_' as u32), ('a' as u32, 'c' as u32),
+    ]));
+    assert_eq!(parse("[^\"]").unwrap(), Regex::Class(vec![
+        (0, '"' as u32 - 1), ('"' as u32 + 1, MAX_CHAR),
+    ]));
+    assert_eq!(parse("\\*?").unwrap(), Regex::Opt(Box::new(class('*', '*'))));
+    assert_eq!(parse("").unwrap(), Regex::Empty);
+
+    match parse("\\p{White_Space}").unwrap() {
+        Regex::Class(class) => {
+            assert_eq!(class[..2], [(0x09, 0x0D), (0x20, 0x20)]);
+            assert!(class.contains(&(0x3000, 0x3000)));
+        }
+        regex => panic!("{:?}", regex),
+    }
+
+    for &invalid in ["(a", "a)", "[a", "*", "[z-a]", "\\q", "\\p{Foo}", "\\pL"].iter() {
+        assert!(parse(invalid).is_err(), "{:?} should be invalid", invalid);
+    }
+}
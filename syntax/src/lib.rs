@@ -4,27 +4,36 @@ use std::fmt;
 
 mod token;
 mod rst;
+mod diagnostics;
+mod line_index;
+mod language;
+mod ast;
+mod lexer;
 
-pub use token::{TokenFile, Token, Tokenizer, TokenBuilder};
+pub use token::{TokenFile, Token, Tokens, TokenStream, TokenBuilder, Checkpoint};
 
-pub use rst::{RstFile, Node, Parser, RstBuilder};
+pub use lexer::Lexer;
 
+pub use diagnostics::{Diagnostic, Diagnostics, Label, Severity};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub struct NodeType(pub u32, pub &'static str);
+pub use line_index::{LineIndex, LineCol};
 
-pub const ERROR: NodeType = NodeType(0, "error");
-pub const WHITESPACE: NodeType = NodeType(1, "whitespace");
+pub use language::{Language, Reparser, FIRST_LANGUAGE_ID};
 
-impl NodeType {
-    pub fn name(&self) -> &'static str {
-        self.1
-    }
-}
+pub use ast::AstElement;
+
+pub use rst::{RstFile, Node, RstBuilder, Marker, CompletedMarker, WalkEvent, TokenAtOffset};
+
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NodeType(pub u32);
+
+pub const ERROR: NodeType = NodeType(0);
+pub const WHITESPACE: NodeType = NodeType(1);
 
 impl fmt::Debug for NodeType {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "NodeType({})", self.name())
+        write!(fmt, "NodeType({})", self.0)
     }
 }
 
@@ -42,9 +51,18 @@ impl Range {
         self.hi
     }
     pub fn from_to(lo: u32, hi: u32) -> Range {
-        assert!(lo < hi);
+        assert!(lo <= hi);
         Range { lo: lo, hi: hi }
     }
+    pub fn empty_at(offset: u32) -> Range {
+        Range { lo: offset, hi: offset }
+    }
+    pub fn len(&self) -> u32 {
+        self.hi - self.lo
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lo == self.hi
+    }
 }
 
 impl std::ops::Index<Range> for str {
@@ -69,17 +87,97 @@ impl fmt::Debug for Range {
 }
 
 
-pub fn check_tokenizer(tokenizer: &Tokenizer, text: &str, expected: &str) {
+pub fn check_tokenizer(lang: &'static Language, text: &str, expected: &str) {
     let text = text.trim();
-    let f = TokenFile::new(text.to_owned(), tokenizer);
+    let f = lang.tokenize(text.to_owned());
     let actual = f.dump();
     assert!(actual.trim() == expected.trim(), "\nInput:\n{}\n\nOutput:\n{}\n\nExpected:\n{}", text, actual, expected);
 }
 
-pub fn check_parser(tokenizer: &Tokenizer, parser: &Parser, file_type: NodeType, text: &str, expected: &str) {
+pub fn check_parser(lang: &'static Language, text: &str, expected: &str) {
     let text = text.trim();
-    let f = TokenFile::new(text.to_owned(), tokenizer);
-    let f = RstFile::new(f, file_type, parser);
+    let f = lang.parse(text.to_owned());
     let actual = f.dump();
     assert!(actual.trim() == expected.trim(), "\nInput:\n{}\n\nOutput:\n{}\n\nExpected:\n{}\n", text, actual, expected);
 }
+
+/// Applies a sequence of pseudo-random edits to `text`, checking that
+/// incremental reparsing agrees with parsing from scratch, and that at
+/// least `min_incremental` edits are reparsed incrementally.
+///
+/// An edit inserts a fragment between two tokens, deletes a node or
+/// replaces a short range of text. The next edit undoes the last one
+/// unless it is an insertion which keeps the text free of errors, so
+/// that the text grows but stays mostly well-formed.
+pub fn check_incremental(
+    lang: &'static Language,
+    text: &str,
+    fragments: &[&str],
+    n_edits: usize,
+    min_incremental: usize,
+) {
+    let mut rng = 0x2545F4914F6CDD1Du64;
+    let mut next = |n: usize| {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        (rng % n as u64) as usize
+    };
+
+    let mut file = lang.parse(text.to_owned());
+    assert!(!file.diagnostics().has_errors(), "{}", file.diagnostics().render("<text>", text));
+    let mut undo: Option<(Range, String)> = None;
+    let mut n_incremental = 0;
+    for _ in 0..n_edits {
+        let is_undo = undo.is_some();
+        let mut is_insertion = false;
+        let (edit, new_text) = match undo.take() {
+            Some(undo) => undo,
+            None => {
+                let len = file.text().len();
+                let tokens: Vec<Node> = file.root().descendants().filter(|node| node.is_leaf()).collect();
+                match next(3) {
+                    0 => {
+                        is_insertion = true;
+                        let offset = match tokens.get(next(tokens.len() + 1)) {
+                            Some(token) => token.range().lo(),
+                            None => len as u32,
+                        };
+                        (Range::empty_at(offset), fragments[next(fragments.len())].to_owned())
+                    }
+                    1 if !tokens.is_empty() => {
+                        let token = tokens[next(tokens.len())];
+                        let nodes: Vec<Node> = token.ancestors()
+                            .take_while(|node| node.parent().is_some())
+                            .collect();
+                        (nodes[next(nodes.len())].range(), String::new())
+                    }
+                    _ => {
+                        let lo = next(len + 1);
+                        let hi = lo + next((len - lo).min(5) + 1);
+                        (Range::from_to(lo as u32, hi as u32), fragments[next(fragments.len())].to_owned())
+                    }
+                }
+            }
+        };
+
+        let before = file.text().to_owned();
+        file = match file.edit_incremental(edit, &new_text) {
+            Ok(file) => {
+                n_incremental += 1;
+                file
+            }
+            Err(text) => lang.parse(text),
+        };
+        let expected = lang.parse(file.text().to_owned());
+        assert!(file.dump() == expected.dump(),
+                "\nEdit {:?} -> {:?} of:\n{}\n\nIncremental:\n{}\nFrom scratch:\n{}\n",
+                edit, new_text, before, file.dump(), expected.dump());
+        if !is_undo && (!is_insertion || file.diagnostics().has_errors()) {
+            let inserted = Range::from_to(edit.lo(), edit.lo() + new_text.len() as u32);
+            undo = Some((inserted, before[edit].to_owned()));
+        }
+    }
+    assert!(n_incremental >= min_incremental,
+            "only {} of {} edits were reparsed incrementally", n_incremental, n_edits);
+}
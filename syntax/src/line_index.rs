@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+/// Zero-based line and column. Depending on the method which produced
+/// it, `col` is measured either in UTF-8 bytes or in UTF-16 code units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LineCol {
+    pub line: u32,
+    pub col: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct LineIndex {
+    line_starts: Vec<u32>,
+    /// Non-ASCII characters of each line, as `(lo, hi)` byte offsets
+    /// relative to the start of the line.
+    wide_chars: HashMap<u32, Vec<(u32, u32)>>,
+    len: u32,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> LineIndex {
+        let mut line_starts = vec![0];
+        let mut wide_chars = HashMap::new();
+        let mut line = 0;
+        let mut line_start = 0;
+        for (offset, c) in text.char_indices() {
+            let offset = offset as u32;
+            if c == '\n' {
+                line += 1;
+                line_start = offset + 1;
+                line_starts.push(line_start);
+                continue;
+            }
+            if c.len_utf8() > 1 {
+                let lo = offset - line_start;
+                wide_chars.entry(line).or_insert_with(Vec::new)
+                    .push((lo, lo + c.len_utf8() as u32));
+            }
+        }
+        LineIndex {
+            line_starts: line_starts,
+            wide_chars: wide_chars,
+            len: text.len() as u32,
+        }
+    }
+
+    pub fn line_count(&self) -> u32 {
+        self.line_starts.len() as u32
+    }
+
+    pub fn line_start(&self, line: u32) -> u32 {
+        self.line_starts[line as usize]
+    }
+
+    pub fn line_col(&self, offset: u32) -> LineCol {
+        assert!(offset <= self.len, "offset {} is out of bounds", offset);
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        LineCol { line: line as u32, col: offset - self.line_starts[line] }
+    }
+
+    pub fn line_col_utf16(&self, offset: u32) -> LineCol {
+        let LineCol { line, col } = self.line_col(offset);
+        let mut utf16_col = col;
+        for &(lo, hi) in self.wide_chars_of(line) {
+            if lo >= col {
+                break;
+            }
+            utf16_col = utf16_col - (hi - lo) + utf16_len(hi - lo);
+        }
+        LineCol { line: line, col: utf16_col }
+    }
+
+    pub fn offset(&self, line_col: LineCol) -> u32 {
+        self.line_starts[line_col.line as usize] + line_col.col
+    }
+
+    pub fn offset_utf16(&self, line_col: LineCol) -> u32 {
+        let mut col = line_col.col;
+        for &(lo, hi) in self.wide_chars_of(line_col.line) {
+            let utf16_lo = lo - (col - line_col.col);
+            if utf16_lo >= line_col.col {
+                break;
+            }
+            col = col + (hi - lo) - utf16_len(hi - lo);
+        }
+        self.offset(LineCol { line: line_col.line, col: col })
+    }
+
+    fn wide_chars_of(&self, line: u32) -> &[(u32, u32)] {
+        self.wide_chars.get(&line).map(|cs| &cs[..]).unwrap_or(&[])
+    }
+}
+
+fn utf16_len(utf8_len: u32) -> u32 {
+    if utf8_len == 4 { 2 } else { 1 }
+}
+
+#[test]
+fn test_line_index() {
+    let text = "hello\nworld\n\nfoo";
+    let index = LineIndex::new(text);
+    assert_eq!(index.line_count(), 4);
+    let cases = [
+        (0, 0, 0), (4, 0, 4), (5, 0, 5), (6, 1, 0), (11, 1, 5),
+        (12, 2, 0), (13, 3, 0), (16, 3, 3),
+    ];
+    for &(offset, line, col) in cases.iter() {
+        let line_col = LineCol { line: line, col: col };
+        assert_eq!(index.line_col(offset), line_col);
+        assert_eq!(index.line_col_utf16(offset), line_col);
+        assert_eq!(index.offset(line_col), offset);
+        assert_eq!(index.offset_utf16(line_col), offset);
+    }
+}
+
+#[test]
+fn test_line_index_multibyte() {
+    // 'ы' is two bytes and one UTF-16 unit, '𝔸' is four bytes and two units.
+    let text = "x = 'ы𝔸'\nы = 1";
+    let index = LineIndex::new(text);
+    let cases = [
+        // offset, line, utf8 col, utf16 col
+        (0, 0, 0, 0),
+        (5, 0, 5, 5),
+        (7, 0, 7, 6),
+        (11, 0, 11, 8),
+        (12, 0, 12, 9),
+        (13, 1, 0, 0),
+        (15, 1, 2, 1),
+        (19, 1, 6, 5),
+    ];
+    for &(offset, line, col, utf16_col) in cases.iter() {
+        assert_eq!(index.line_col(offset), LineCol { line: line, col: col });
+        assert_eq!(index.line_col_utf16(offset), LineCol { line: line, col: utf16_col });
+        assert_eq!(index.offset(LineCol { line: line, col: col }), offset);
+        assert_eq!(index.offset_utf16(LineCol { line: line, col: utf16_col }), offset);
+    }
+}
@@ -1,10 +1,12 @@
 use data_structures::LazyCell;
 
+use std::collections::VecDeque;
 use std::fmt::{self, Write};
 
-use {NodeType, TokenFile, Token, Range, WHITESPACE};
+use {NodeType, TokenFile, TokenStream, Token, Range, Diagnostic, Diagnostics, LineIndex, Language, ERROR, WHITESPACE};
 
 mod raw;
+mod reparse;
 
 use self::raw::{RawNodes, NodeId, RawNode, RawNodeData, ZERO_NODE_ID};
 
@@ -19,17 +21,18 @@ impl<'f> Node<'f> {
         self.raw().ty
     }
 
-    pub fn children_with_ws(&self) -> ChildrenIterator {
+    pub fn children_with_trivia(&self) -> ChildrenIterator<'f> {
         ChildrenIterator {
-            skip_ws: false,
+            skip_trivia: false,
             file: self.file,
             current: self.raw().first_child(),
         }
     }
 
-    pub fn children(&self) -> ChildrenIterator {
+    /// Children which are not whitespace, comments or other trivia.
+    pub fn children(&self) -> ChildrenIterator<'f> {
         ChildrenIterator {
-            skip_ws: true,
+            skip_trivia: true,
             file: self.file,
             current: self.raw().first_child(),
         }
@@ -39,12 +42,88 @@ impl<'f> Node<'f> {
         &self.file.text[self.range()]
     }
 
-    fn range(&self) -> Range {
-        match self.file.raw(self.id).data {
+    pub fn parent(&self) -> Option<Node<'f>> {
+        self.raw().parent.map(|id| self.file.node(id))
+    }
+
+    pub fn next_sibling(&self) -> Option<Node<'f>> {
+        self.raw().next_sibling.map(|id| self.file.node(id))
+    }
+
+    pub fn prev_sibling(&self) -> Option<Node<'f>> {
+        let parent = self.parent()?;
+        let mut prev = None;
+        for child in parent.children_with_trivia() {
+            if child == *self {
+                return prev;
+            }
+            prev = Some(child);
+        }
+        unreachable!("Node is not a child of its parent")
+    }
+
+    /// Iterates over this node, its parent, grandparent and so on.
+    pub fn ancestors(&self) -> Ancestors<'f> {
+        Ancestors { next: Some(*self) }
+    }
+
+    /// Iterates over this node and all nodes below it in preorder.
+    pub fn descendants(&self) -> Descendants<'f> {
+        Descendants { walk: self.walk() }
+    }
+
+    pub fn walk(&self) -> Walk<'f> {
+        Walk { root: *self, next: Some(WalkEvent::Enter(*self)) }
+    }
+
+    pub fn first_token(&self) -> Option<Node<'f>> {
+        self.descendants().find(|n| n.is_leaf())
+    }
+
+    pub fn last_token(&self) -> Option<Node<'f>> {
+        if self.is_leaf() {
+            return Some(*self);
+        }
+        let mut children: Vec<Node<'f>> = self.children_with_trivia().collect();
+        while let Some(child) = children.pop() {
+            if let Some(token) = child.last_token() {
+                return Some(token);
+            }
+        }
+        None
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        match self.raw().data {
+            RawNodeData::Leaf { .. } => true,
+            RawNodeData::Composite { .. } => false,
+        }
+    }
+
+    /// The original token, if this is a leaf node.
+    pub fn token(&self) -> Option<Token<'f>> {
+        match self.raw().data {
+            RawNodeData::Leaf { range } => Some(Token {
+                ty: self.ty(),
+                text: &self.file.text[range],
+                range: range,
+            }),
+            RawNodeData::Composite { .. } => None,
+        }
+    }
+
+    /// The range of the node including all whitespace inside it. An empty
+    /// composite node has an empty range at the position where it was
+    /// started.
+    pub fn range(&self) -> Range {
+        match self.raw().data {
             RawNodeData::Leaf { range } => range,
-            RawNodeData::Composite { ref range, .. } => range.get(|| {
-                let mut children = self.children_with_ws();
-                let first = children.next().unwrap().range();
+            RawNodeData::Composite { ref range, offset, .. } => range.get(|| {
+                let mut children = self.children_with_trivia();
+                let first = match children.next() {
+                    Some(child) => child.range(),
+                    None => return Range::empty_at(offset),
+                };
                 let lo = first.lo();
                 let mut hi = first.hi();
                 for child in children {
@@ -57,7 +136,19 @@ impl<'f> Node<'f> {
         }
     }
 
-    fn raw(&self) -> &RawNode {
+    /// The range of the node without leading and trailing trivia.
+    pub fn text_range(&self) -> Range {
+        let lang = self.file.lang;
+        let significant = |node: &Node| node.is_leaf() && !lang.is_trivia(node.ty());
+        let first = self.descendants().find(&significant);
+        let last = self.descendants().filter(&significant).last();
+        match (first, last) {
+            (Some(first), Some(last)) => Range::from_to(first.range().lo(), last.range().hi()),
+            _ => Range::empty_at(self.range().lo()),
+        }
+    }
+
+    fn raw(&self) -> &'f RawNode {
         self.file.raw(self.id)
     }
 
@@ -65,61 +156,175 @@ impl<'f> Node<'f> {
         for _ in 0..level {
             buff.push_str("  ");
         }
-        buff.push_str(self.ty().name());
+        buff.push_str(self.file.lang.node_name(self.ty()));
         match self.raw().data {
             RawNodeData::Leaf { range } =>
                 write!(buff, " {:?}", &self.file.text[range]).expect("write to string can't fail"),
             RawNodeData::Composite { .. } => {}
         }
         buff.push('\n');
-        for child in self.children_with_ws() {
+        for child in self.children_with_trivia() {
             child.dump(buff, level + 1);
         }
     }
 }
 
+impl<'f> PartialEq for Node<'f> {
+    fn eq(&self, other: &Node<'f>) -> bool {
+        ::std::ptr::eq(self.file, other.file) && self.id == other.id
+    }
+}
+
+impl<'f> Eq for Node<'f> {}
+
 impl<'f> fmt::Debug for Node<'f> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        self.ty().fmt(fmt)
+        write!(fmt, "NodeType({})", self.file.lang.node_name(self.ty()))
     }
 }
 
 pub struct RstFile {
+    lang: &'static Language,
     text: String,
-    nodes: RawNodes
+    nodes: RawNodes,
+    diagnostics: Diagnostics,
+    /// For each diagnostic, the node which was being parsed when it was
+    /// reported, or `None` if it comes from the tokenizer.
+    owners: Vec<Option<NodeId>>,
 }
 
 
 impl RstFile {
-    pub fn new(file: TokenFile, file_type: NodeType, parser: &Parser) -> RstFile {
-        let nodes = {
-            let tokens = file.tokens();
-            let mut builder = RstBuilder::new(&tokens);
-            builder.start(file_type);
-            builder.skip_ws();
-            parser(&mut builder);
-            builder.skip_ws();
-            builder.finish(file_type);
-            assert!(builder.stack.is_empty());
-            builder.into_nodes()
+    pub fn new(file: TokenFile) -> RstFile {
+        let lang = file.language();
+        let (nodes, errors) = RstFile::build(lang, Box::new(file.tokens()));
+        let (text, lexer_diagnostics) = file.into_parts();
+        RstFile::from_parser(lang, text, nodes, lexer_diagnostics, errors)
+    }
+
+    /// Parses `text`, tokenizing it lazily as the parser goes.
+    pub fn parse(lang: &'static Language, text: String) -> RstFile {
+        let (nodes, errors, lexer_diagnostics) = {
+            let mut stream = TokenStream::new(&text, lang);
+            // Rebuild the tokens, so that they borrow the text only as long
+            // as the builder borrows the stream.
+            let tokens = stream.by_ref()
+                .map(|t| Token { ty: t.ty, text: t.text, range: t.range });
+            let (nodes, errors) = RstFile::build(lang, Box::new(tokens));
+            (nodes, errors, stream.into_diagnostics())
         };
+        RstFile::from_parser(lang, text, nodes, lexer_diagnostics, errors)
+    }
 
+    fn build<'t>(lang: &'static Language, tokens: Box<Iterator<Item=Token<'t>> + 't>)
+        -> (RawNodes, Vec<(NodeId, Diagnostic)>)
+    {
+        let mut builder = RstBuilder::new(lang, tokens);
+        let file = builder.start(lang.file_type);
+        (lang.parser)(&mut builder);
+        if builder.peek().is_some() {
+            builder.error("unexpected token");
+            let error = builder.start(ERROR);
+            while builder.peek().is_some() {
+                builder.bump();
+            }
+            builder.complete(error);
+        }
+        builder.complete(file);
+        assert!(builder.stack.is_empty());
+        builder.into_nodes()
+    }
+
+    fn from_parser(
+        lang: &'static Language,
+        text: String,
+        nodes: RawNodes,
+        lexer_diagnostics: Diagnostics,
+        errors: Vec<(NodeId, Diagnostic)>,
+    ) -> RstFile {
+        let diagnostics = lexer_diagnostics.into_vec().into_iter()
+            .map(|d| (None, d))
+            .chain(errors.into_iter().map(|(owner, d)| (Some(owner), d)))
+            .collect();
+        RstFile::from_parts(lang, text, nodes, diagnostics)
+    }
+
+    fn from_parts(
+        lang: &'static Language,
+        text: String,
+        nodes: RawNodes,
+        mut diagnostics: Vec<(Option<NodeId>, Diagnostic)>,
+    ) -> RstFile {
+        diagnostics.sort_by_key(|d| d.1.range.lo());
+        let owners = diagnostics.iter().map(|&(owner, _)| owner).collect();
+        let mut sink = Diagnostics::new();
+        for (_, d) in diagnostics {
+            sink.push(d);
+        }
         RstFile {
-            text: file.into_text(),
-            nodes: nodes
+            lang: lang,
+            text: text,
+            nodes: nodes,
+            diagnostics: sink,
+            owners: owners,
         }
     }
 
     pub fn dump(&self) -> String {
         let mut buff = String::new();
-        for child in self.root().children_with_ws() {
+        for child in self.root().children_with_trivia() {
             child.dump(&mut buff, 0);
         }
+        buff.push_str(&self.diagnostics.dump());
         buff
     }
 
+    pub fn language(&self) -> &'static Language {
+        self.lang
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn line_index(&self) -> LineIndex {
+        LineIndex::new(&self.text)
+    }
+
     pub fn root(&self) -> Node {
-        Node { file: self, id: ZERO_NODE_ID }
+        self.node(ZERO_NODE_ID)
+    }
+
+    /// Finds the token which contains `offset`. If `offset` is on the
+    /// boundary between two tokens, both are returned.
+    pub fn token_at_offset(&self, offset: u32) -> TokenAtOffset {
+        token_at_offset(self.root(), offset)
+    }
+
+    /// Finds the innermost node which contains `range`. An empty range on
+    /// the boundary between two nodes is covered by the left one.
+    pub fn covering_node(&self, range: Range) -> Node {
+        let root = self.root();
+        assert!(range.hi() as usize <= self.text.len(), "range {:?} is out of bounds", range);
+        let mut node = root;
+        'descend: loop {
+            for child in node.children_with_trivia() {
+                let r = child.range();
+                if r.lo() <= range.lo() && range.hi() <= r.hi() {
+                    node = child;
+                    continue 'descend;
+                }
+            }
+            return node;
+        }
+    }
+
+    fn node(&self, id: NodeId) -> Node {
+        Node { file: self, id: id }
     }
 
     fn raw(&self, id: NodeId) -> &RawNode {
@@ -128,7 +333,7 @@ impl RstFile {
 }
 
 pub struct ChildrenIterator<'f> {
-    skip_ws: bool,
+    skip_trivia: bool,
     file: &'f RstFile,
     current: Option<NodeId>
 }
@@ -137,8 +342,8 @@ impl<'f> Iterator for ChildrenIterator<'f> {
     type Item = Node<'f>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.skip_ws {
-            self.skip_ws()
+        if self.skip_trivia {
+            self.skip_trivia()
         }
         if let Some(id) = self.current.take() {
             self.current = self.file.raw(id).next_sibling;
@@ -159,10 +364,10 @@ impl<'f> ChildrenIterator<'f> {
         assert!(self.current.is_none());
     }
 
-    fn skip_ws(&mut self) {
+    fn skip_trivia(&mut self) {
         while let Some(node) = self.current {
             let node = self.file.raw(node);
-            if node.ty != WHITESPACE {
+            if !self.file.lang.is_trivia(node.ty) {
                 break
             }
             self.current = node.next_sibling
@@ -170,19 +375,170 @@ impl<'f> ChildrenIterator<'f> {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenAtOffset<'f> {
+    None,
+    Single(Node<'f>),
+    Between(Node<'f>, Node<'f>),
+}
+
+impl<'f> TokenAtOffset<'f> {
+    pub fn left_biased(self) -> Option<Node<'f>> {
+        match self {
+            TokenAtOffset::None => None,
+            TokenAtOffset::Single(node) | TokenAtOffset::Between(node, _) => Some(node),
+        }
+    }
+
+    pub fn right_biased(self) -> Option<Node<'f>> {
+        match self {
+            TokenAtOffset::None => None,
+            TokenAtOffset::Single(node) | TokenAtOffset::Between(_, node) => Some(node),
+        }
+    }
+}
+
+fn token_at_offset(node: Node, offset: u32) -> TokenAtOffset {
+    let range = node.range();
+    if offset < range.lo() || range.hi() < offset {
+        return TokenAtOffset::None;
+    }
+    if node.is_leaf() {
+        return TokenAtOffset::Single(node);
+    }
+    let mut candidates = node.children_with_trivia()
+        .filter(|child| child.first_token().is_some())
+        .filter(|child| {
+            let r = child.range();
+            r.lo() <= offset && offset <= r.hi()
+        });
+    match (candidates.next(), candidates.next()) {
+        (None, _) => TokenAtOffset::None,
+        (Some(child), None) => token_at_offset(child, offset),
+        (Some(left), Some(right)) => {
+            let left = token_at_offset(left, offset).right_biased();
+            let right = token_at_offset(right, offset).left_biased();
+            match (left, right) {
+                (Some(left), Some(right)) => TokenAtOffset::Between(left, right),
+                (Some(token), None) | (None, Some(token)) => TokenAtOffset::Single(token),
+                (None, None) => TokenAtOffset::None,
+            }
+        }
+    }
+}
+
+pub struct Ancestors<'f> {
+    next: Option<Node<'f>>,
+}
+
+impl<'f> Iterator for Ancestors<'f> {
+    type Item = Node<'f>;
+
+    fn next(&mut self) -> Option<Node<'f>> {
+        let node = self.next.take()?;
+        self.next = node.parent();
+        Some(node)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalkEvent<'f> {
+    Enter(Node<'f>),
+    Leave(Node<'f>),
+}
+
+/// Preorder traversal, which reports both entering and leaving a node.
+pub struct Walk<'f> {
+    root: Node<'f>,
+    next: Option<WalkEvent<'f>>,
+}
+
+impl<'f> Iterator for Walk<'f> {
+    type Item = WalkEvent<'f>;
+
+    fn next(&mut self) -> Option<WalkEvent<'f>> {
+        let event = self.next.take()?;
+        self.next = match event {
+            WalkEvent::Enter(node) => match node.children_with_trivia().next() {
+                Some(child) => Some(WalkEvent::Enter(child)),
+                None => Some(WalkEvent::Leave(node)),
+            },
+            WalkEvent::Leave(node) if node == self.root => None,
+            WalkEvent::Leave(node) => match node.next_sibling() {
+                Some(sibling) => Some(WalkEvent::Enter(sibling)),
+                None => node.parent().map(WalkEvent::Leave),
+            },
+        };
+        Some(event)
+    }
+}
+
+pub struct Descendants<'f> {
+    walk: Walk<'f>,
+}
+
+impl<'f> Iterator for Descendants<'f> {
+    type Item = Node<'f>;
+
+    fn next(&mut self) -> Option<Node<'f>> {
+        loop {
+            match self.walk.next()? {
+                WalkEvent::Enter(node) => return Some(node),
+                WalkEvent::Leave(_) => {}
+            }
+        }
+    }
+}
 
+/// A node which is started, but not yet completed.
+#[must_use = "a started node must be completed"]
+pub struct Marker {
+    id: NodeId,
+}
 
-pub type Parser = Fn(&mut RstBuilder);
+/// A completed node, which can be wrapped into a new one.
+pub struct CompletedMarker {
+    id: NodeId,
+}
 
-#[derive(Debug)]
 pub struct RstBuilder<'f> {
-    tokens: &'f [Token<'f>],
+    lang: &'static Language,
+    tokens: Box<Iterator<Item=Token<'f>> + 'f>,
+    /// The first significant token after the last bumped one.
+    next: Option<Token<'f>>,
+    /// The end of the last bumped token.
+    last_hi: u32,
+    /// The number of consumed tokens, trivia included.
     pos: usize,
+    /// Trivia which is already skipped, but not yet added to the tree.
+    /// Leading comments are attached to the next started node if it's one
+    /// of `Language::comment_owners`, the rest goes to the parent, so nodes
+    /// never end with trivia.
+    trivia: Vec<Token<'f>>,
     nodes: RawNodes,
     stack: Vec<Frame>,
+    diagnostics: Vec<(NodeId, Diagnostic)>,
+    /// The number of active `try_parse` calls.
+    n_tries: usize,
+    /// Tokens pulled from `tokens` since the outermost `try_parse`
+    /// started, to be replayed if it fails.
+    taken: Vec<Token<'f>>,
+    replay: VecDeque<Token<'f>>,
 }
 
-#[derive(Debug)]
+/// The state of a `RstBuilder` to roll back to.
+struct Snapshot<'f> {
+    next: Option<Token<'f>>,
+    last_hi: u32,
+    pos: usize,
+    trivia: Vec<Token<'f>>,
+    n_nodes: usize,
+    stack: Vec<Frame>,
+    n_diagnostics: usize,
+    n_taken: usize,
+}
+
+#[derive(Debug, Clone)]
 struct Frame {
     parent: NodeId,
     last_child: Option<NodeId>,
@@ -200,12 +556,12 @@ impl Frame {
         self.add_child(nodes, id);
     }
 
-    fn new_composite_node(&mut self, nodes: &mut RawNodes, ty: NodeType) -> NodeId {
+    fn new_composite_node(&mut self, nodes: &mut RawNodes, ty: NodeType, offset: u32) -> NodeId {
         let id = nodes.push(RawNode {
             ty: ty,
             parent: Some(self.parent),
             next_sibling: None,
-            data: RawNodeData::Composite { first_child: None, range: LazyCell::new() }
+            data: RawNodeData::Composite { first_child: None, range: LazyCell::new(), offset: offset }
         });
 
         self.add_child(nodes, id);
@@ -224,95 +580,446 @@ impl Frame {
 
 impl<'f> RstBuilder<'f> {
     pub fn peek(&self) -> Option<NodeType> {
-        if self.pos < self.tokens.len() {
-            Some(self.tokens[self.pos].ty)
-        } else {
-            None
-        }
+        self.next.map(|t| t.ty)
     }
 
     pub fn bump(&mut self) {
-        let token = {
-            if self.pos >= self.tokens.len() {
-                panic!("EOF")
+        let token = match self.next {
+            Some(token) => token,
+            None => {
+                self.error("unexpected end of file");
+                return;
             }
-            self.pos += 1;
-            self.tokens[self.pos - 1]
         };
+        self.flush_trivia();
+        {
+            let frame = self.stack.last_mut()
+                .expect("Token without parent");
+            frame.new_leaf_node(&mut self.nodes, token);
+        }
+        self.advance();
+    }
 
-        let frame = self.stack.last_mut()
-            .expect("Token without parent");
+    pub fn eat(&mut self, ty: NodeType) -> bool {
+        if self.peek() == Some(ty) {
+            self.bump();
+            return true;
+        }
+        let name = self.lang.node_name(ty);
+        self.error(format!("expected {}", name));
+        false
+    }
 
-        frame.new_leaf_node(&mut self.nodes, token);
+    /// Records an error at the start of the next token.
+    pub fn error<M: Into<String>>(&mut self, message: M) {
+        let offset = self.offset();
+        self.diagnostic(Diagnostic::error(Range::empty_at(offset), message));
     }
 
-    pub fn eat(&mut self, ty: NodeType) {
-        match self.peek() {
-            Some(t) if t == ty => self.bump(),
-            None => panic!("EOF"),
-            Some(t) => panic!("Expected {:?}, got {:?}", ty, t),
+    /// Records an error and wraps the next token, if any, into an `ERROR`
+    /// node.
+    pub fn err_and_bump<M: Into<String>>(&mut self, message: M) {
+        match self.next {
+            Some(token) => self.diagnostic(Diagnostic::error(token.range, message)),
+            None => return self.error(message),
         }
+        let m = self.start(ERROR);
+        self.bump();
+        self.complete(m);
     }
 
-    pub fn skip_ws(&mut self) {
-        while let Some(t) = self.peek() {
-            if t != WHITESPACE {
-                break;
-            }
-
-            self.bump()
+    /// Runs `f`, undoing everything it has done if it returns `false`.
+    /// Allows to parse constructs which can't be recognized by looking
+    /// at the next token only.
+    pub fn try_parse<F: FnOnce(&mut RstBuilder<'f>) -> bool>(&mut self, f: F) -> bool {
+        let snapshot = self.snapshot();
+        self.n_tries += 1;
+        let success = f(self);
+        self.n_tries -= 1;
+        if !success {
+            self.rollback(snapshot);
+        }
+        if self.n_tries == 0 {
+            self.taken.clear();
         }
+        success
+    }
+
+    /// Adds an empty node as a placeholder for something which should
+    /// have been here, but isn't.
+    pub fn missing(&mut self, ty: NodeType) {
+        self.flush_trivia();
+        let m = self.start(ty);
+        self.complete(m);
     }
 
-    pub fn start(&mut self, ty: NodeType) {
+    /// Starts a new node, which receives all the following tokens and
+    /// nodes until the returned marker is completed.
+    pub fn start(&mut self, ty: NodeType) -> Marker {
+        let offset = self.offset();
+        let leading = if self.stack.is_empty() {
+            0
+        } else if self.lang.comment_owners.contains(&ty) {
+            self.leading_comments()
+        } else {
+            self.trivia.len()
+        };
+        let inner = self.trivia.split_off(leading);
+        self.flush_trivia();
+        self.trivia = inner;
         if self.stack.is_empty() {
-            let id = self.new_composite_node(None, ty);
+            let id = self.new_composite_node(None, ty, offset);
             self.stack.push(Frame {
                 parent: id,
                 last_child: None,
             });
-            return
+            return Marker { id: id };
         }
 
         let id = self.stack
             .last_mut().unwrap()
-            .new_composite_node(&mut self.nodes, ty);
+            .new_composite_node(&mut self.nodes, ty, offset);
 
         self.stack.push(Frame {
             parent: id,
             last_child: None,
         });
+        self.flush_trivia();
+        Marker { id: id }
     }
 
-    pub fn finish(&mut self, ty: NodeType) {
+    /// Finishes the node started by `marker`. All nodes started after it
+    /// must be already completed.
+    pub fn complete(&mut self, marker: Marker) -> CompletedMarker {
+        if self.stack.len() == 1 {
+            self.flush_trivia();
+        }
         let frame = self.stack.pop()
             .expect("Empty parent stack");
-        assert_eq!(self.node_mut(frame.parent).ty, ty);
+        assert_eq!(frame.parent, marker.id, "Nodes must be completed in reverse order");
+        CompletedMarker { id: marker.id }
     }
 
-    fn new(tokens: &'f [Token<'f>]) -> RstBuilder<'f> {
-        RstBuilder {
+    /// Starts a new node and moves the `completed` one into it, so that
+    /// `a` in `a + b` can be parsed before it's known to be an operand.
+    /// Nothing should be added after `completed` before this call.
+    pub fn precede(&mut self, completed: CompletedMarker, ty: NodeType) -> Marker {
+        let child = completed.id;
+        let offset = match self.nodes[child].data {
+            RawNodeData::Composite { offset, .. } => offset,
+            RawNodeData::Leaf { .. } => unreachable!(),
+        };
+        let id = self.new_composite_node(None, ty, offset);
+        {
+            let frame = self.stack.last_mut()
+                .expect("Empty parent stack");
+            assert_eq!(frame.last_child, Some(child), "Only the last node can be preceded");
+            frame.last_child = Some(id);
+        }
+        self.nodes.replace_child(child, id);
+        self.nodes[child].parent = Some(id);
+        self.nodes[child].next_sibling = None;
+        self.nodes[id].set_first_child(child);
+        self.stack.push(Frame {
+            parent: id,
+            last_child: Some(child),
+        });
+        Marker { id: id }
+    }
+
+    fn new(lang: &'static Language, tokens: Box<Iterator<Item=Token<'f>> + 'f>) -> RstBuilder<'f> {
+        let mut builder = RstBuilder {
+            lang: lang,
+            next: None,
             tokens: tokens,
+            last_hi: 0,
+            trivia: Vec::new(),
             pos: 0,
             nodes: RawNodes::new(),
             stack: Vec::new(),
+            diagnostics: Vec::new(),
+            n_tries: 0,
+            taken: Vec::new(),
+            replay: VecDeque::new(),
+        };
+        builder.next = builder.next_significant();
+        builder
+    }
+
+    fn advance(&mut self) {
+        if let Some(token) = self.next {
+            self.last_hi = token.range.hi();
+            self.pos += 1;
+            self.next = self.next_significant();
         }
     }
 
-    fn into_nodes(self) -> RawNodes {
-        self.nodes
+    /// Pulls tokens until a non trivia one, buffering the trivia.
+    fn next_significant(&mut self) -> Option<Token<'f>> {
+        while let Some(token) = self.pull() {
+            if !self.lang.is_trivia(token.ty) {
+                return Some(token);
+            }
+            self.last_hi = token.range.hi();
+            self.pos += 1;
+            self.trivia.push(token);
+        }
+        None
     }
 
-    fn node_mut(&mut self, id: NodeId) -> &mut RawNode {
-        &mut self.nodes[id]
+    fn pull(&mut self) -> Option<Token<'f>> {
+        let token = match self.replay.pop_front() {
+            Some(token) => Some(token),
+            None => self.tokens.next(),
+        };
+        if self.n_tries > 0 {
+            self.taken.extend(token);
+        }
+        token
     }
 
-    fn new_composite_node(&mut self, parent: Option<NodeId>, ty: NodeType) -> NodeId {
+    fn snapshot(&self) -> Snapshot<'f> {
+        Snapshot {
+            next: self.next,
+            last_hi: self.last_hi,
+            pos: self.pos,
+            trivia: self.trivia.clone(),
+            n_nodes: self.nodes.len(),
+            stack: self.stack.clone(),
+            n_diagnostics: self.diagnostics.len(),
+            n_taken: self.taken.len(),
+        }
+    }
+
+    fn rollback(&mut self, snapshot: Snapshot<'f>) {
+        for &token in self.taken[snapshot.n_taken..].iter().rev() {
+            self.replay.push_front(token);
+        }
+        self.taken.truncate(snapshot.n_taken);
+        self.next = snapshot.next;
+        self.last_hi = snapshot.last_hi;
+        self.pos = snapshot.pos;
+        self.trivia = snapshot.trivia;
+        self.nodes.truncate(snapshot.n_nodes);
+        self.diagnostics.truncate(snapshot.n_diagnostics);
+        // Links from the surviving nodes to the new ones are in the
+        // open nodes: either after their last child, or in place of it
+        // if it was preceded.
+        for frame in snapshot.stack.iter() {
+            let last = match frame.last_child {
+                Some(last) => last,
+                None => {
+                    self.nodes[frame.parent].reset_first_child(None);
+                    continue;
+                }
+            };
+            self.nodes[last].parent = Some(frame.parent);
+            self.nodes[last].next_sibling = None;
+            let mut prev = None;
+            let mut child = self.nodes[frame.parent].first_child();
+            while let Some(c) = child {
+                if c == last || c.0 as usize >= snapshot.n_nodes {
+                    break;
+                }
+                prev = Some(c);
+                child = self.nodes[c].next_sibling;
+            }
+            match prev {
+                Some(prev) => self.nodes[prev].next_sibling = Some(last),
+                None => self.nodes[frame.parent].set_first_child(last),
+            }
+        }
+        self.stack = snapshot.stack;
+    }
+
+    /// The number of tokens which are already added to the tree.
+    fn n_attached(&self) -> usize {
+        self.pos - self.trivia.len()
+    }
+
+    fn flush_trivia(&mut self) {
+        if self.trivia.is_empty() {
+            return;
+        }
+        let frame = self.stack.last_mut()
+            .expect("Token without parent");
+        for token in self.trivia.drain(..) {
+            frame.new_leaf_node(&mut self.nodes, token);
+        }
+    }
+
+    /// Returns the index of the first skipped trivia token which belongs
+    /// to the next node: a run of comments right before the node, with
+    /// no blank lines between them and the node.
+    fn leading_comments(&self) -> usize {
+        let mut result = self.trivia.len();
+        for (idx, token) in self.trivia.iter().enumerate().rev() {
+            if token.ty != WHITESPACE {
+                result = idx;
+            } else if token.text.matches('\n').count() > 1 {
+                break;
+            }
+        }
+        result
+    }
+
+    fn into_nodes(self) -> (RawNodes, Vec<(NodeId, Diagnostic)>) {
+        (self.nodes, self.diagnostics)
+    }
+
+    fn diagnostic(&mut self, diagnostic: Diagnostic) {
+        let owner = self.stack.last()
+            .expect("Error without parent")
+            .parent;
+        self.diagnostics.push((owner, diagnostic));
+    }
+
+    fn offset(&self) -> u32 {
+        match self.next {
+            Some(token) => token.range.lo(),
+            None => self.last_hi,
+        }
+    }
+
+    fn new_composite_node(&mut self, parent: Option<NodeId>, ty: NodeType, offset: u32) -> NodeId {
         self.nodes.push(RawNode {
             ty: ty,
             parent: parent,
             next_sibling: None,
-            data: RawNodeData::Composite { first_child: None, range: LazyCell::new() }
+            data: RawNodeData::Composite { first_child: None, range: LazyCell::new(), offset: offset }
         })
     }
 }
+
+#[cfg(test)]
+mod test_lang {
+    use {NodeType, Language, Lexer, TokenBuilder, RstBuilder, CompletedMarker, FIRST_LANGUAGE_ID, WHITESPACE};
+
+    pub const NUMBER: NodeType = NodeType(FIRST_LANGUAGE_ID);
+    pub const MINUS: NodeType = NodeType(FIRST_LANGUAGE_ID + 1);
+    pub const FILE: NodeType = NodeType(FIRST_LANGUAGE_ID + 2);
+    pub const LITERAL: NodeType = NodeType(FIRST_LANGUAGE_ID + 3);
+    pub const BIN_EXPR: NodeType = NodeType(FIRST_LANGUAGE_ID + 4);
+
+    pub static LANG: Language = Language {
+        name: "test",
+        node_types: &[
+            (NUMBER, "number"), (MINUS, "-"), (FILE, "file"),
+            (LITERAL, "literal"), (BIN_EXPR, "bin_expr"),
+        ],
+        file_type: FILE,
+        trivia: &[WHITESPACE],
+        comment_owners: &[],
+        tokenizer: tokenize,
+        parser: parse,
+        reparsers: &[(BIN_EXPR, parse)],
+    };
+
+    /// Rejects every token, to check `err_and_bump`.
+    pub static ERRORS: Language = Language {
+        name: "errors",
+        node_types: &[(NUMBER, "number"), (MINUS, "-"), (FILE, "file")],
+        file_type: FILE,
+        trivia: &[WHITESPACE],
+        comment_owners: &[],
+        tokenizer: tokenize,
+        parser: parse_errors,
+        reparsers: &[],
+    };
+
+    fn tokenize(builder: &mut TokenBuilder) {
+        thread_local! {
+            static LEXER: Lexer = Lexer::new(&[
+                (NUMBER, r"\d+"), (MINUS, "-"), (WHITESPACE, r"\s+"),
+            ]);
+        }
+        LEXER.with(|lexer| lexer.next_token(builder))
+    }
+
+    fn parse(builder: &mut RstBuilder) {
+        let mut lhs = match literal(builder) {
+            Some(lhs) => lhs,
+            None => return,
+        };
+        while builder.peek() == Some(MINUS) {
+            let m = builder.precede(lhs, BIN_EXPR);
+            builder.bump();
+            literal(builder);
+            lhs = builder.complete(m);
+        }
+    }
+
+    fn parse_errors(builder: &mut RstBuilder) {
+        builder.err_and_bump("expected nothing");
+        builder.err_and_bump("expected nothing");
+    }
+
+    fn literal(builder: &mut RstBuilder) -> Option<CompletedMarker> {
+        if builder.peek() != Some(NUMBER) {
+            builder.error("expected a number");
+            return None;
+        }
+        let m = builder.start(LITERAL);
+        builder.bump();
+        Some(builder.complete(m))
+    }
+}
+
+#[test]
+fn test_precede() {
+    ::check_parser(&test_lang::LANG, "1 - 2 -3", r#"
+bin_expr
+  bin_expr
+    literal
+      number "1"
+    whitespace " "
+    - "-"
+    whitespace " "
+    literal
+      number "2"
+  whitespace " "
+  - "-"
+  literal
+    number "3"
+"#);
+    ::check_parser(&test_lang::LANG, "1 - - 2", r#"
+bin_expr
+  bin_expr
+    literal
+      number "1"
+    whitespace " "
+    - "-"
+  whitespace " "
+  - "-"
+  whitespace " "
+  literal
+    number "2"
+error [4; 4): expected a number
+"#);
+}
+
+#[test]
+fn test_err_and_bump() {
+    ::check_parser(&test_lang::ERRORS, "92", r#"
+error
+  number "92"
+error [0; 2): expected nothing
+error [2; 2): expected nothing
+"#);
+}
+
+#[test]
+fn test_edit_drops_replaced_nodes() {
+    let mut file = test_lang::LANG.parse("1 - 2 - 3".to_owned());
+    let n_nodes = file.nodes.len();
+    for i in 0..1000 {
+        let (range, new_text) = if i % 2 == 0 {
+            (Range::from_to(4, 5), "42")
+        } else {
+            (Range::from_to(4, 6), "2")
+        };
+        file = file.edit_incremental(range, new_text).expect("incremental reparse");
+        assert!(file.nodes.len() <= 2 * n_nodes, "{} nodes after {} edits", file.nodes.len(), i + 1);
+    }
+    assert_eq!(file.dump(), test_lang::LANG.parse(file.text().to_owned()).dump());
+}
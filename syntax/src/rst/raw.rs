@@ -3,12 +3,14 @@ use {NodeType, Range};
 
 #[derive(Debug)]
 pub struct RawNodes {
-    data: Vec<RawNode>
+    data: Vec<RawNode>,
+    /// Nodes which are no longer reachable from the root.
+    n_dead: usize,
 }
 
 impl RawNodes {
     pub fn new() -> RawNodes {
-        RawNodes { data: Vec::new() }
+        RawNodes { data: Vec::new(), n_dead: 0 }
     }
 
     pub fn push(&mut self, node: RawNode) -> NodeId {
@@ -16,6 +18,126 @@ impl RawNodes {
         self.data.push(node);
         result
     }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn n_dead(&self) -> usize {
+        self.n_dead
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len)
+    }
+
+    /// Appends all nodes from `other`, returning the offset which should
+    /// be added to `other`'s ids.
+    pub fn append(&mut self, other: RawNodes) -> u32 {
+        let offset = self.data.len() as u32;
+        let shift = |id: Option<NodeId>| id.map(|NodeId(id)| NodeId(id + offset));
+        for mut node in other.data {
+            node.parent = shift(node.parent);
+            node.next_sibling = shift(node.next_sibling);
+            if let RawNodeData::Composite { ref mut first_child, .. } = node.data {
+                *first_child = shift(*first_child);
+            }
+            self.data.push(node);
+        }
+        offset
+    }
+
+    /// Puts `new` in place of `old` in the list of children of `old`'s parent.
+    pub fn replace_child(&mut self, old: NodeId, new: NodeId) {
+        let parent = self[old].parent.expect("Can't replace the root");
+        self[new].parent = Some(parent);
+        self[new].next_sibling = self[old].next_sibling;
+        let mut prev = None;
+        let mut child = self[parent].first_child();
+        while let Some(c) = child {
+            if c == old {
+                break;
+            }
+            prev = Some(c);
+            child = self[c].next_sibling;
+        }
+        match prev {
+            Some(prev) => self[prev].next_sibling = Some(new),
+            None => self[parent].set_first_child(new),
+        }
+    }
+
+    /// Like `replace_child`, but `old` and its descendants are dropped:
+    /// they stay in place until the next `compact`.
+    pub fn replace_subtree(&mut self, old: NodeId, new: NodeId) {
+        self.replace_child(old, new);
+        let mut stack = vec![old];
+        while let Some(id) = stack.pop() {
+            self.n_dead += 1;
+            let mut child = self[id].first_child();
+            while let Some(c) = child {
+                stack.push(c);
+                child = self[c].next_sibling;
+            }
+        }
+    }
+
+    /// Removes the nodes which are not reachable from the root, keeping
+    /// the order of the rest. Returns the new id of each old node.
+    pub fn compact(&mut self) -> Vec<Option<NodeId>> {
+        let mut live = vec![false; self.data.len()];
+        let mut stack = vec![ZERO_NODE_ID];
+        while let Some(id) = stack.pop() {
+            live[id.0 as usize] = true;
+            let mut child = self[id].first_child();
+            while let Some(c) = child {
+                stack.push(c);
+                child = self[c].next_sibling;
+            }
+        }
+        let mut new_ids = vec![None; live.len()];
+        let mut n_live = 0;
+        for (i, _) in live.iter().enumerate().filter(|&(_, &live)| live) {
+            new_ids[i] = Some(NodeId(n_live));
+            n_live += 1;
+        }
+
+        let new_id = |id: Option<NodeId>| id.map(|NodeId(id)| new_ids[id as usize].unwrap());
+        let old = ::std::mem::replace(&mut self.data, Vec::with_capacity(n_live as usize));
+        for (mut node, live) in old.into_iter().zip(live) {
+            if !live {
+                continue;
+            }
+            node.parent = new_id(node.parent);
+            node.next_sibling = new_id(node.next_sibling);
+            if let RawNodeData::Composite { ref mut first_child, .. } = node.data {
+                *first_child = new_id(*first_child);
+            }
+            self.data.push(node);
+        }
+        self.n_dead = 0;
+        new_ids
+    }
+}
+
+/// Clones the nodes, dropping cached ranges of composite nodes.
+impl Clone for RawNodes {
+    fn clone(&self) -> RawNodes {
+        let data = self.data.iter().map(|node| RawNode {
+            ty: node.ty,
+            parent: node.parent,
+            next_sibling: node.next_sibling,
+            data: match node.data {
+                RawNodeData::Leaf { range } => RawNodeData::Leaf { range: range },
+                RawNodeData::Composite { first_child, offset, .. } => RawNodeData::Composite {
+                    first_child: first_child,
+                    range: LazyCell::new(),
+                    offset: offset,
+                },
+            },
+        }).collect();
+        RawNodes { data: data, n_dead: self.n_dead }
+    }
 }
 
 impl ::std::ops::Index<NodeId> for RawNodes {
@@ -31,8 +153,8 @@ impl ::std::ops::IndexMut<NodeId> for RawNodes {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct NodeId(u32);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(pub u32);
 
 pub const ZERO_NODE_ID: NodeId = NodeId(0);
 
@@ -52,6 +174,8 @@ pub enum RawNodeData {
     Composite {
         first_child: Option<NodeId>,
         range: LazyCell<Range>,
+        /// Position of the node if it has no children.
+        offset: u32,
     }
 }
 
@@ -64,9 +188,13 @@ impl RawNode {
     }
 
     pub fn set_first_child(&mut self, id: NodeId) {
+        self.reset_first_child(Some(id))
+    }
+
+    pub fn reset_first_child(&mut self, id: Option<NodeId>) {
         match self.data {
             RawNodeData::Leaf { .. } => panic!("Leaf node can't have children"),
-            RawNodeData::Composite { ref mut first_child, .. } => *first_child = Some(id),
+            RawNodeData::Composite { ref mut first_child, .. } => *first_child = id,
         }
     }
 }
@@ -0,0 +1,327 @@
+use std::collections::HashSet;
+use std::iter;
+
+use data_structures::LazyCell;
+use {NodeType, Range, Token, Diagnostic, Language};
+
+use super::{RstFile, RstBuilder, Node, TokenAtOffset};
+use super::raw::{RawNodes, NodeId, RawNodeData, ZERO_NODE_ID};
+
+impl RstFile {
+    /// Replaces `range` of the text with `new_text`.
+    ///
+    /// Only the tokens around the edit are relexed, and only the smallest
+    /// node from `Language::reparsers` which contains all changed tokens
+    /// is reparsed, the rest of the nodes are reused in place. If there's
+    /// no such node, the whole file is parsed from scratch.
+    ///
+    /// Panics if `range` is out of bounds or doesn't lie on char
+    /// boundaries.
+    pub fn edit(self, range: Range, new_text: &str) -> RstFile {
+        let lang = self.lang;
+        match self.edit_incremental(range, new_text) {
+            Ok(file) => file,
+            Err(text) => lang.parse(text),
+        }
+    }
+
+    /// Like `edit`, but returns the new text instead of parsing it from
+    /// scratch if the edit can't be reparsed incrementally.
+    pub(crate) fn edit_incremental(self, range: Range, new_text: &str) -> Result<RstFile, String> {
+        assert!(range.hi() as usize <= self.text.len(), "edit: range {:?} is out of bounds", range);
+        assert!(self.text.is_char_boundary(range.lo() as usize) && self.text.is_char_boundary(range.hi() as usize),
+                "edit: range {:?} is not on char boundaries", range);
+        let mut text = String::with_capacity(self.text.len() + new_text.len() - range.len() as usize);
+        text.push_str(&self.text[..range.lo() as usize]);
+        text.push_str(new_text);
+        text.push_str(&self.text[range.hi() as usize..]);
+
+        let reparsed = match reparse(&self, self.lang, range, &text) {
+            Some(reparsed) => reparsed,
+            None => return Err(text),
+        };
+        let RstFile { lang, mut nodes, .. } = self;
+        let offset = nodes.append(reparsed.nodes);
+        let new_node = NodeId(reparsed.new_node.0 + offset);
+        nodes.replace_subtree(reparsed.old_node, new_node);
+        shift_after(&mut nodes, new_node, reparsed.delta);
+        let mut diagnostics = reparsed.diagnostics;
+        // Compacting renumbers all nodes, so do it only once the dead
+        // nodes outnumber the live ones.
+        if 2 * nodes.n_dead() > nodes.len() {
+            let new_ids = nodes.compact();
+            for &mut (ref mut owner, _) in diagnostics.iter_mut() {
+                *owner = owner.map(|NodeId(id)| new_ids[id as usize].expect("diagnostic of a removed node"));
+            }
+        }
+        Ok(RstFile::from_parts(lang, text, nodes, diagnostics))
+    }
+}
+
+type RawToken = (NodeType, Range);
+
+struct Reparsed {
+    /// The node which is replaced.
+    old_node: NodeId,
+    /// The new nodes, to be appended to the nodes of the file.
+    nodes: RawNodes,
+    /// The replacement node among `nodes`.
+    new_node: NodeId,
+    /// How much the text after the edit moves.
+    delta: i64,
+    /// All diagnostics of the new file, with owners from `nodes` already
+    /// shifted by the length of the old nodes.
+    diagnostics: Vec<(Option<NodeId>, Diagnostic)>,
+}
+
+fn reparse(file: &RstFile, lang: &'static Language, edit: Range, text: &str) -> Option<Reparsed> {
+    let delta = text.len() as i64 - file.text.len() as i64;
+
+    // Relex the tokens touched by the edit plus one token on the right,
+    // which must come out unchanged for the window to be in sync with
+    // the rest of the file.
+    let first = match file.token_at_offset(edit.lo()) {
+        TokenAtOffset::None => return None,
+        TokenAtOffset::Single(token) | TokenAtOffset::Between(token, _) => token,
+    };
+    let last = match file.token_at_offset(edit.hi()) {
+        TokenAtOffset::None => return None,
+        TokenAtOffset::Single(token) | TokenAtOffset::Between(_, token) => token,
+    };
+    let mut window_leaves = vec![first];
+    while *window_leaves.last().unwrap() != last {
+        let leaf = next_leaf(*window_leaves.last().unwrap())?;
+        window_leaves.push(leaf);
+    }
+    let has_next = match next_leaf(last) {
+        Some(leaf) => {
+            window_leaves.push(leaf);
+            true
+        }
+        None => false,
+    };
+    let old_tokens: Vec<RawToken> = window_leaves.iter()
+        .map(|leaf| (leaf.ty(), leaf.range()))
+        .collect();
+    let old_window = Range::from_to(old_tokens[0].1.lo(), old_tokens[old_tokens.len() - 1].1.hi());
+    let window = Range::from_to(old_window.lo(), shift_offset(old_window.hi(), delta));
+    let (relexed, lexer_diagnostics) = {
+        let file = lang.tokenize(text[window].to_owned());
+        let tokens: Vec<RawToken> = file.tokens()
+            .map(|t| (t.ty, shift(t.range, window.lo() as i64)))
+            .collect();
+        let (_, diagnostics) = file.into_parts();
+        (tokens, diagnostics)
+    };
+    if has_next {
+        let (old_ty, old_range) = old_tokens[old_tokens.len() - 1];
+        match relexed.last() {
+            Some(&(ty, range)) if ty == old_ty && range.len() == old_range.len() => {}
+            _ => return None,
+        }
+    }
+
+    // Find the tokens of the window which have actually changed: old
+    // `[a, b)` became new `[a, a + n_changed)`.
+    let same = |old: &RawToken, new: &RawToken| {
+        old.0 == new.0 && file.text[old.1] == text[new.1]
+    };
+    let prefix = old_tokens.iter().zip(relexed.iter())
+        .take_while(|&(old, new)| same(old, new))
+        .count();
+    let max_suffix = old_tokens.len().min(relexed.len()) - prefix;
+    let suffix = old_tokens.iter().rev().zip(relexed.iter().rev())
+        .take(max_suffix)
+        .take_while(|&(old, new)| same(old, new))
+        .count();
+    let a = prefix;
+    let b = old_tokens.len() - suffix;
+    let changed = &relexed[prefix..relexed.len() - suffix];
+    // Everything at or after `cut` in the old text moves by `delta`.
+    let cut = if a < b {
+        old_tokens[b - 1].1.hi()
+    } else {
+        match window_leaves.get(a).cloned().or_else(|| next_leaf(window_leaves[a - 1])) {
+            Some(leaf) => leaf.range().lo(),
+            None => file.text.len() as u32,
+        }
+    };
+
+    let anchor = if a < b {
+        window_leaves[a]
+    } else if a > 0 {
+        window_leaves[a - 1]
+    } else {
+        prev_leaf(window_leaves[0])?
+    };
+
+    for node in anchor.ancestors().skip(1) {
+        let reparser = match lang.reparser(node.ty()) {
+            Some(reparser) => reparser,
+            None => continue,
+        };
+        // The node must keep its first token, and contain all changed
+        // ones: its old leaves `[..ia]` come before the change, and
+        // `[ib..]` after it.
+        let node_leaves: Vec<Node> = node.descendants().filter(|n| n.is_leaf()).collect();
+        let i = node_leaves.iter().position(|&leaf| leaf == anchor).unwrap();
+        let ia = if a < b { i } else { i + 1 };
+        let ib = ia + (b - a);
+        if ia == 0 || ib > node_leaves.len() {
+            continue;
+        }
+
+        let token = |ty: NodeType, range: Range| Token { ty: ty, text: &text[range], range: range };
+        let mut tokens: Vec<Token> = Vec::with_capacity(node_leaves.len() - (b - a) + changed.len());
+        tokens.extend(node_leaves[..ia].iter().map(|leaf| token(leaf.ty(), leaf.range())));
+        tokens.extend(changed.iter().map(|&(ty, range)| token(ty, range)));
+        tokens.extend(node_leaves[ib..].iter().map(|leaf| token(leaf.ty(), shift(leaf.range(), delta))));
+        // The parser may look past the end of the node.
+        let lookahead = iter::successors(next_leaf(node_leaves[node_leaves.len() - 1]), |&leaf| next_leaf(leaf))
+            .map(|leaf| token(leaf.ty(), shift(leaf.range(), delta)));
+        let mut builder = RstBuilder::new(lang, Box::new(tokens.iter().cloned().chain(lookahead)));
+        let root = builder.start(lang.file_type);
+        reparser(&mut builder);
+        if builder.n_attached() != tokens.len()
+            || builder.diagnostics.iter().any(|&(owner, _)| owner == ZERO_NODE_ID) {
+            continue;
+        }
+        let new_node = match builder.nodes[ZERO_NODE_ID].first_child() {
+            Some(child) if builder.nodes[child].next_sibling.is_none()
+                && builder.nodes[child].ty == node.ty() => child,
+            _ => continue,
+        };
+        // An error of an outer node, reported at the start of the next
+        // token, might move if that token is inside the reparsed node.
+        let subtree: HashSet<NodeId> = node.descendants().map(|n| n.id).collect();
+        let old_range = node.range();
+        let moves = file.owners.iter().zip(file.diagnostics.iter())
+            .any(|(&owner, d)| match owner {
+                Some(owner) => !subtree.contains(&owner)
+                    && old_range.lo() <= d.range.hi() && d.range.lo() <= old_range.hi(),
+                None => false,
+            });
+        if moves {
+            continue;
+        }
+        builder.complete(root);
+        let (sub_nodes, sub_diagnostics) = builder.into_nodes();
+        let offset = file.nodes.len() as u32;
+
+        // Keep the order `RstFile::new` would produce: lexer diagnostics
+        // first, then parser ones in the order they were reported.
+        let new_lo = tokens[0].range.lo();
+        let mut lexer = Vec::new();
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+        for (&owner, d) in file.owners.iter().zip(file.diagnostics.iter()) {
+            match owner {
+                None if old_window.lo() <= d.range.lo() && d.range.lo() < old_window.hi()
+                    && d.range.hi() <= old_window.hi() => continue,
+                Some(owner) if subtree.contains(&owner) => continue,
+                _ => {}
+            }
+            let mut d = d.clone();
+            if d.range.lo() >= cut {
+                d.range = shift(d.range, delta);
+                for label in d.labels.iter_mut() {
+                    label.range = shift(label.range, delta);
+                }
+            }
+            match owner {
+                None => lexer.push((owner, d)),
+                Some(_) if d.range.lo() <= new_lo => before.push((owner, d)),
+                Some(_) => after.push((owner, d)),
+            }
+        }
+        lexer.extend(lexer_diagnostics.into_vec().into_iter()
+            .map(|mut d| {
+                d.range = shift(d.range, window.lo() as i64);
+                (None, d)
+            }));
+        lexer.extend(before);
+        lexer.extend(sub_diagnostics.into_iter()
+            .map(|(owner, d)| (Some(NodeId(owner.0 + offset)), d)));
+        lexer.extend(after);
+        return Some(Reparsed {
+            old_node: node.id,
+            nodes: sub_nodes,
+            new_node: new_node,
+            delta: delta,
+            diagnostics: lexer,
+        });
+    }
+    None
+}
+
+/// Moves the nodes to the right of `id` by `delta`, and drops the cached
+/// ranges of `id`'s ancestors.
+fn shift_after(nodes: &mut RawNodes, id: NodeId, delta: i64) {
+    let mut stack = Vec::new();
+    let mut node = id;
+    while let Some(parent) = nodes[node].parent {
+        let mut sibling = nodes[node].next_sibling;
+        while let Some(id) = sibling {
+            stack.push(id);
+            sibling = nodes[id].next_sibling;
+        }
+        if let RawNodeData::Composite { ref mut range, .. } = nodes[parent].data {
+            *range = LazyCell::new();
+        }
+        node = parent;
+    }
+    while let Some(id) = stack.pop() {
+        let first_child = match nodes[id].data {
+            RawNodeData::Leaf { ref mut range } => {
+                *range = shift(*range, delta);
+                None
+            }
+            RawNodeData::Composite { first_child, ref mut range, ref mut offset } => {
+                *range = LazyCell::new();
+                *offset = shift_offset(*offset, delta);
+                first_child
+            }
+        };
+        let mut child = first_child;
+        while let Some(id) = child {
+            stack.push(id);
+            child = nodes[id].next_sibling;
+        }
+    }
+}
+
+fn next_leaf(node: Node) -> Option<Node> {
+    let mut node = node;
+    loop {
+        let mut sibling = node.next_sibling();
+        while let Some(s) = sibling {
+            if let Some(leaf) = s.first_token() {
+                return Some(leaf);
+            }
+            sibling = s.next_sibling();
+        }
+        node = node.parent()?;
+    }
+}
+
+fn prev_leaf(node: Node) -> Option<Node> {
+    let mut node = node;
+    loop {
+        let mut sibling = node.prev_sibling();
+        while let Some(s) = sibling {
+            if let Some(leaf) = s.last_token() {
+                return Some(leaf);
+            }
+            sibling = s.prev_sibling();
+        }
+        node = node.parent()?;
+    }
+}
+
+fn shift_offset(offset: u32, delta: i64) -> u32 {
+    (offset as i64 + delta) as u32
+}
+
+fn shift(range: Range, delta: i64) -> Range {
+    Range::from_to(shift_offset(range.lo(), delta), shift_offset(range.hi(), delta))
+}
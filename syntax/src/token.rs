@@ -1,7 +1,7 @@
-use std::str::Chars;
-use std::iter::Peekable;
+use std::collections::VecDeque;
+use std::slice;
 
-use super::{NodeType, Range};
+use super::{NodeType, Range, Diagnostics, LineIndex, Language, Lexer};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Token<'file> {
@@ -11,73 +11,180 @@ pub struct Token<'file> {
 }
 
 pub struct TokenFile {
+    lang: &'static Language,
     text: String,
-    tokens: Vec<RawToken>
+    tokens: Vec<RawToken>,
+    diagnostics: Diagnostics,
 }
 
 impl TokenFile {
-    pub fn new(text: String, tokenizer: &Tokenizer) -> TokenFile {
-        let tokens = {
-            let chars = text.chars().peekable();
-            let mut builder = TokenBuilder::new(chars);
-            tokenizer(&mut builder);
-            builder.into_tokens()
+    pub fn new(text: String, lang: &'static Language) -> TokenFile {
+        let (tokens, diagnostics) = {
+            let mut stream = TokenStream::new(&text, lang);
+            let tokens = stream.by_ref()
+                .map(|t| RawToken { ty: t.ty, range: t.range })
+                .collect();
+            (tokens, stream.into_diagnostics())
         };
         TokenFile {
+            lang: lang,
             text: text,
             tokens: tokens,
+            diagnostics: diagnostics,
         }
     }
 
-    pub fn tokens(&self) -> Vec<Token> {
-        self.tokens.iter().map(|t| {
-            Token {
-                ty: t.ty,
-                text: &self.text[t.range],
-                range: t.range,
-            }
-        }).collect()
+    pub fn tokens(&self) -> Tokens {
+        Tokens { text: &self.text, raw: self.tokens.iter() }
     }
 
     pub fn dump(&self) -> String {
-        self.tokens().iter()
-            .map(|t| format!("{} {:?}\n", t.ty.name(), t.text))
-            .collect()
+        let mut buff: String = self.tokens()
+            .map(|t| format!("{} {:?}\n", self.lang.node_name(t.ty), t.text))
+            .collect();
+        buff.push_str(&self.diagnostics.dump());
+        buff
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn language(&self) -> &'static Language {
+        self.lang
     }
 
-    pub fn into_text(self) -> String {
-        self.text
+    pub fn line_index(&self) -> LineIndex {
+        LineIndex::new(&self.text)
     }
+
+    pub fn into_parts(self) -> (String, Diagnostics) {
+        (self.text, self.diagnostics)
+    }
+}
+
+pub struct Tokens<'f> {
+    text: &'f str,
+    raw: slice::Iter<'f, RawToken>,
 }
 
-pub type Tokenizer = Fn(&mut TokenBuilder);
+impl<'f> Iterator for Tokens<'f> {
+    type Item = Token<'f>;
 
-type CharIter<'a> = Peekable<Chars<'a>>;
+    fn next(&mut self) -> Option<Token<'f>> {
+        self.raw.next().map(|t| t.token(self.text))
+    }
+}
+
+/// Tokens of a text, produced lazily as they are requested.
+pub struct TokenStream<'t> {
+    lang: &'static Language,
+    builder: TokenBuilder<'t>,
+}
+
+impl<'t> TokenStream<'t> {
+    pub fn new(text: &'t str, lang: &'static Language) -> TokenStream<'t> {
+        TokenStream { lang: lang, builder: TokenBuilder::new(text) }
+    }
+
+    /// Diagnostics for the tokens produced so far.
+    pub fn into_diagnostics(self) -> Diagnostics {
+        self.builder.diagnostics
+    }
+}
+
+impl<'t> Iterator for TokenStream<'t> {
+    type Item = Token<'t>;
+
+    fn next(&mut self) -> Option<Token<'t>> {
+        while self.builder.tokens.is_empty() {
+            self.builder.peek()?;
+            let offset = self.builder.curr_offset;
+            (self.lang.tokenizer)(&mut self.builder);
+            assert!(self.builder.curr_offset > offset || !self.builder.tokens.is_empty(),
+                    "{} tokenizer made no progress at {}", self.lang.name, offset);
+        }
+        self.builder.n_drained += 1;
+        let text = self.builder.text;
+        self.builder.tokens.pop_front().map(|t| t.token(text))
+    }
+}
 
 pub struct TokenBuilder<'a> {
-    chars: CharIter<'a>,
-    tokens: Vec<RawToken>,
+    text: &'a str,
+    keywords: Option<(NodeType, &'a [(NodeType, &'a str)])>,
+    /// Tokens which are not yet taken by the `TokenStream`.
+    tokens: VecDeque<RawToken>,
+    n_drained: usize,
+    diagnostics: Diagnostics,
+    prev_offset: u32,
+    curr_offset: u32,
+}
+
+/// A position in the input to return to with `TokenBuilder::rewind`.
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint {
     prev_offset: u32,
     curr_offset: u32,
+    n_tokens: usize,
+    n_diagnostics: usize,
 }
 
 impl<'a> TokenBuilder<'a> {
-    pub fn peek(&mut self) -> Option<char> {
-        self.chars.peek().cloned()
+    pub fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// Looks `n` characters ahead, `peek_nth(0)` is the same as `peek()`.
+    pub fn peek_nth(&self, n: usize) -> Option<char> {
+        self.rest().chars().nth(n)
+    }
+
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        self.rest().starts_with(prefix)
     }
 
     pub fn bump(&mut self) {
-        let c = self.chars.next().expect("EOF");
+        let c = self.peek().expect("EOF");
         self.curr_offset += c.len_utf8() as u32
     }
 
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            prev_offset: self.prev_offset,
+            curr_offset: self.curr_offset,
+            n_tokens: self.n_drained + self.tokens.len(),
+            n_diagnostics: self.diagnostics.len(),
+        }
+    }
+
+    /// Forgets everything bumped, emitted and reported after `checkpoint`.
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        assert!(self.n_drained <= checkpoint.n_tokens && checkpoint.curr_offset <= self.curr_offset,
+                "rewinding to a checkpoint which is already gone");
+        self.prev_offset = checkpoint.prev_offset;
+        self.curr_offset = checkpoint.curr_offset;
+        self.tokens.truncate(checkpoint.n_tokens - self.n_drained);
+        self.diagnostics.truncate(checkpoint.n_diagnostics);
+    }
+
+    /// Emits a token spanning the characters bumped since the previous
+    /// one. The token may be empty.
     pub fn emit(&mut self, ty: NodeType) {
+        let range = Range::from_to(self.prev_offset, self.curr_offset);
+        let ty = match self.keywords {
+            Some((ident, keywords)) if ty == ident => keywords.iter()
+                .find(|&&(_, keyword)| keyword == &self.text[range])
+                .map(|&(keyword_ty, _)| keyword_ty)
+                .unwrap_or(ty),
+            _ => ty,
+        };
         let token = RawToken {
             ty: ty,
-            range: Range::from_to(self.prev_offset, self.curr_offset),
+            range: range,
         };
         self.prev_offset = self.curr_offset;
-        self.tokens.push(token)
+        self.tokens.push_back(token)
     }
 
     pub fn try_text_token(&mut self, tokens: &[(NodeType, char)]) -> bool {
@@ -96,6 +203,29 @@ impl<'a> TokenBuilder<'a> {
         false
     }
 
+    /// Emits the longest of `tokens` which the rest of the input starts with.
+    pub fn try_text_tokens(&mut self, tokens: &[(NodeType, &str)]) -> bool {
+        let (ty, len) = {
+            let rest = self.rest();
+            // `rev` makes `max_by_key` pick the first of equally long tokens.
+            match tokens.iter().rev()
+                .filter(|&&(_, text)| !text.is_empty() && rest.starts_with(text))
+                .max_by_key(|&&(_, text)| text.len()) {
+                Some(&(ty, text)) => (ty, text.len() as u32),
+                None => return false,
+            }
+        };
+        self.curr_offset += len;
+        self.emit(ty);
+        true
+    }
+
+    /// Makes tokens of type `ident` which are spelled as one of the
+    /// `keywords` be emitted with the type of the keyword instead.
+    pub fn set_keywords(&mut self, ident: NodeType, keywords: &'a [(NodeType, &'a str)]) {
+        self.keywords = Some((ident, keywords));
+    }
+
     pub fn try_pred_token(&mut self, tokens: &[(NodeType, &Fn(char) -> bool)]) -> bool {
         let next = match self.peek() {
             Some(n) => n,
@@ -117,22 +247,53 @@ impl<'a> TokenBuilder<'a> {
         true
     }
 
+    /// Emits the longest token recognized by `lexer`, if any.
+    pub fn try_lex(&mut self, lexer: &Lexer) -> bool {
+        let (rule, len) = match lexer.longest_match_rule(self.rest().chars()) {
+            Some(m) => m,
+            None => return false,
+        };
+        self.curr_offset += len as u32;
+        match rule {
+            Ok(::ERROR) => self.error(),
+            Ok(ty) => self.emit(ty),
+            Err(message) => self.error_with(message),
+        }
+        true
+    }
+
     pub fn error(&mut self) {
+        self.error_with("invalid token");
+    }
+
+    /// Emits an `ERROR` token spanning the characters bumped since the
+    /// previous token and reports `message` for it.
+    pub fn error_with<M: Into<String>>(&mut self, message: M) {
+        let range = Range::from_to(self.prev_offset, self.curr_offset);
+        self.diagnostics.error(range, message);
         self.emit(::ERROR);
     }
 
-    fn new(chars: CharIter) -> TokenBuilder {
+    pub fn diagnostics(&mut self) -> &mut Diagnostics {
+        &mut self.diagnostics
+    }
+
+    fn new(text: &str) -> TokenBuilder {
         TokenBuilder {
-            chars: chars,
-            tokens: Vec::new(),
+            text: text,
+            keywords: None,
+            tokens: VecDeque::new(),
+            n_drained: 0,
+            diagnostics: Diagnostics::new(),
             prev_offset: 0,
             curr_offset: 0,
         }
     }
 
-    fn into_tokens(self) -> Vec<RawToken> {
-        self.tokens
+    fn rest(&self) -> &'a str {
+        &self.text[self.curr_offset as usize..]
     }
+
 }
 
 #[derive(Clone, Copy)]
@@ -140,3 +301,148 @@ struct RawToken {
     ty: NodeType,
     range: Range,
 }
+
+impl RawToken {
+    fn token(self, text: &str) -> Token {
+        Token { ty: self.ty, text: &text[self.range], range: self.range }
+    }
+}
+
+#[cfg(test)]
+mod test_lang {
+    use {NodeType, Language, TokenBuilder, RstBuilder, FIRST_LANGUAGE_ID, WHITESPACE};
+
+    pub const EQ: NodeType = NodeType(FIRST_LANGUAGE_ID);
+    pub const EQ_EQ: NodeType = NodeType(FIRST_LANGUAGE_ID + 1);
+    pub const LT: NodeType = NodeType(FIRST_LANGUAGE_ID + 2);
+    pub const LT_EQ: NodeType = NodeType(FIRST_LANGUAGE_ID + 3);
+    pub const ARROW: NodeType = NodeType(FIRST_LANGUAGE_ID + 4);
+    pub const MINUS: NodeType = NodeType(FIRST_LANGUAGE_ID + 5);
+    pub const ID: NodeType = NodeType(FIRST_LANGUAGE_ID + 6);
+    pub const IF: NodeType = NodeType(FIRST_LANGUAGE_ID + 7);
+    pub const WHILE: NodeType = NodeType(FIRST_LANGUAGE_ID + 8);
+    pub const FILE: NodeType = NodeType(FIRST_LANGUAGE_ID + 9);
+    pub const NUMBER: NodeType = NodeType(FIRST_LANGUAGE_ID + 10);
+    pub const DOT: NodeType = NodeType(FIRST_LANGUAGE_ID + 11);
+    pub const SLASH: NodeType = NodeType(FIRST_LANGUAGE_ID + 12);
+    pub const COMMENT: NodeType = NodeType(FIRST_LANGUAGE_ID + 13);
+
+    pub static LANG: Language = Language {
+        name: "test",
+        node_types: &[
+            (EQ, "="), (EQ_EQ, "=="), (LT, "<"), (LT_EQ, "<="), (ARROW, "->"), (MINUS, "-"),
+            (ID, "id"), (IF, "if"), (WHILE, "while"), (FILE, "file"),
+            (NUMBER, "number"), (DOT, "."), (SLASH, "/"), (COMMENT, "comment"),
+        ],
+        file_type: FILE,
+        trivia: &[WHITESPACE, COMMENT],
+        comment_owners: &[],
+        tokenizer: tokenize,
+        parser: parse,
+        reparsers: &[],
+    };
+
+    fn tokenize(builder: &mut TokenBuilder) {
+        builder.set_keywords(ID, &[(IF, "if"), (WHILE, "while")]);
+        let text_tokens = [
+            (EQ, "="), (EQ_EQ, "=="), (LT, "<"), (LT_EQ, "<="), (MINUS, "-"), (ARROW, "->"),
+            (DOT, "."),
+        ];
+        let pred_tokens: &[(NodeType, &Fn(char) -> bool)] = &[
+            (ID, &char::is_alphabetic),
+            (WHITESPACE, &char::is_whitespace),
+        ];
+        while builder.peek().is_some() {
+            if number(builder) || comment(builder) {
+                continue;
+            }
+            if !builder.try_text_tokens(&text_tokens) && !builder.try_pred_token(pred_tokens) {
+                builder.bump();
+                builder.error();
+            }
+        }
+    }
+
+    /// `1.5` is a single number, but `1.foo` is a number and a dot.
+    fn number(builder: &mut TokenBuilder) -> bool {
+        let is_digit = |c: Option<char>| c.map(|c| c.is_ascii_digit()).unwrap_or(false);
+        if !is_digit(builder.peek()) {
+            return false;
+        }
+        while is_digit(builder.peek()) {
+            builder.bump();
+        }
+        if builder.peek() == Some('.') && is_digit(builder.peek_nth(1)) {
+            builder.bump();
+            while is_digit(builder.peek()) {
+                builder.bump();
+            }
+        }
+        builder.emit(NUMBER);
+        true
+    }
+
+    /// An unterminated `/*` is lexed as a slash and whatever follows.
+    fn comment(builder: &mut TokenBuilder) -> bool {
+        if !builder.starts_with("/") {
+            return false;
+        }
+        let checkpoint = builder.checkpoint();
+        builder.bump();
+        if builder.starts_with("*") {
+            while builder.peek().is_some() {
+                if builder.starts_with("*/") {
+                    builder.bump();
+                    builder.bump();
+                    builder.emit(COMMENT);
+                    return true;
+                }
+                builder.bump();
+            }
+            builder.rewind(checkpoint);
+            builder.bump();
+        }
+        builder.emit(SLASH);
+        true
+    }
+
+    fn parse(_: &mut RstBuilder) {}
+}
+
+#[test]
+fn test_text_tokens_and_keywords() {
+    ::check_tokenizer(&test_lang::LANG, "if ifx <= == - -> whilewhile", r#"
+if "if"
+whitespace " "
+id "ifx"
+whitespace " "
+<= "<="
+whitespace " "
+== "=="
+whitespace " "
+- "-"
+whitespace " "
+-> "->"
+whitespace " "
+id "whilewhile"
+"#);
+}
+
+#[test]
+fn test_lookahead_and_rewind() {
+    ::check_tokenizer(&test_lang::LANG, "1.5 1.ы /*ы*/ /* ы", r#"
+number "1.5"
+whitespace " "
+number "1"
+. "."
+id "ы"
+whitespace " "
+comment "/*ы*/"
+whitespace " "
+/ "/"
+error "*"
+whitespace " "
+id "ы"
+error [17; 18): invalid token
+"#);
+}